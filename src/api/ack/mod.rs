@@ -0,0 +1,64 @@
+use actix_web::{
+    HttpResponse,
+    web::{
+        Json
+    }
+};
+use crate::{
+    api::msg::acknowledge,
+    leases::Token,
+    stores::ScopedData
+};
+use serde::{
+    Deserialize, 
+    Serialize
+};
+use std::time::Instant;
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Body {
+    pub lease: String
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum Reply {
+    Ok { uuid: String }
+}
+
+/// Acknowledges a claimed message, deleting it from the store.
+///
+/// A message claimed for a consumer group is only deleted once every consumer group has acked it.
+///
+/// Leases that have run out can no longer be acked since the message may already be with another consumer.
+pub fn post(data: ScopedData, body: Json<Body>) -> HttpResponse {
+    let token = match Token::parse(&body.lease) {
+        Some(token) => token,
+        None => {
+            return HttpResponse::BadRequest().finish();
+        }
+    };
+    let mut store = match data.store.try_lock() {
+        Ok(store) => store,
+        Err(_error) => {
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let released = match data.leases.try_lock() {
+        Ok(mut leases) => leases.release(&token, Instant::now()),
+        Err(_error) => {
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let lease = match released {
+        Some(lease) => lease,
+        None => {
+            return HttpResponse::NotFound().finish();
+        }
+    };
+    match acknowledge(&data, &mut store, &lease.uuid, lease.consumer_group.as_deref()) {
+        Ok(true) => HttpResponse::Ok().json(Reply::Ok { uuid: lease.uuid.to_string() }),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(_error) => HttpResponse::InternalServerError().finish()
+    }
+}
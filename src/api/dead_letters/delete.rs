@@ -0,0 +1,68 @@
+use actix_web::{
+    HttpResponse,
+    web::{
+        Query
+    }
+};
+use crate::{
+    api::{
+        dead_letters::{
+            Info,
+            selected
+        },
+        msg::remove
+    },
+    events::EventKind,
+    stores::ScopedData
+};
+use serde::{
+    Deserialize, 
+    Serialize
+};
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum Reply {
+    Ok { deleted: Vec<String> }
+}
+
+/// Deletes the dead letter given by uuid, or all of them if none is given.
+///
+/// Messages that are not dead letters are left alone.
+pub fn delete(data: ScopedData, info: Query<Info>) -> HttpResponse {
+    let mut store = match data.store.try_lock() {
+        Ok(store) => store,
+        Err(_error) => {
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let uuids = match selected(&data, &info) {
+        Some(Ok(uuids)) => uuids,
+        Some(Err(())) => {
+            return HttpResponse::InternalServerError().finish();
+        },
+        None => {
+            return HttpResponse::BadRequest().finish();
+        }
+    };
+    let mut deleted = vec![];
+    for uuid in uuids {
+        let is_dead = match data.meta.try_lock() {
+            Ok(meta) => meta.is_dead(&uuid),
+            Err(_error) => {
+                return HttpResponse::InternalServerError().finish();
+            }
+        };
+        if !is_dead {
+            continue;
+        }
+        match remove(&data, &mut store, &uuid, EventKind::Deleted) {
+            Ok(true) => deleted.push(uuid.to_string()),
+            Ok(false) => (),
+            Err(_error) => {
+                return HttpResponse::InternalServerError().finish();
+            }
+        }
+    }
+    HttpResponse::Ok().json(Reply::Ok { deleted })
+}
@@ -0,0 +1,54 @@
+use actix_web::HttpResponse;
+use crate::{
+    api::msg::msg_event,
+    stores::ScopedData
+};
+use serde::{
+    Deserialize, 
+    Serialize
+};
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeadLetterData {
+    uuid: String,
+    priority: u32,
+    original_priority: u32,
+    attempts: u32,
+    byte_size: u32
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum Reply {
+    Ok { data: Vec<DeadLetterData> }
+}
+
+/// Lists the messages that have been set aside after too many failed delivery attempts.
+pub fn get(data: ScopedData) -> HttpResponse {
+    let store = match data.store.try_lock() {
+        Ok(store) => store,
+        Err(_error) => {
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let dead_letters = match data.meta.try_lock() {
+        Ok(meta) => meta.dead_letters(),
+        Err(_error) => {
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let data = dead_letters.into_iter()
+        .filter_map(|(uuid, dead_letter)| {
+            let msg = msg_event(&store, &uuid)?;
+            Some(DeadLetterData {
+                uuid: uuid.to_string(),
+                priority: msg.priority,
+                original_priority: dead_letter.priority,
+                attempts: dead_letter.attempts,
+                byte_size: msg.byte_size
+            })
+        })
+        .collect();
+    HttpResponse::Ok().json(Reply::Ok { data })
+}
@@ -0,0 +1,155 @@
+pub mod delete;
+pub mod get;
+pub mod put;
+
+use crate::{
+    api::parse_uuid,
+    AppData
+};
+use msg_store::Uuid;
+use serde::{
+    Deserialize, 
+    Serialize
+};
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Info {
+    uuid: Option<String>
+}
+
+/// The dead letters a request is about, either the one asked for by uuid or all of them.
+///
+/// Returns None if the uuid is not valid.
+fn selected(data: &AppData, info: &Info) -> Option<Result<Vec<Uuid>, ()>> {
+    match &info.uuid {
+        Some(uuid) => parse_uuid(uuid).map(|uuid| Ok(vec![uuid])),
+        None => match data.meta.try_lock() {
+            Ok(meta) => Some(Ok(meta.dead_letters().into_iter().map(|(uuid, _dead_letter)| uuid).collect())),
+            Err(_error) => Some(Err(()))
+        }
+    }
+}
+
+#[cfg(all(test, feature = "mem"))]
+mod tests {
+    use super::*;
+    use actix_web::{
+        dev::Payload,
+        http::StatusCode,
+        test::TestRequest,
+        web::{
+            Data,
+            Query
+        },
+        FromRequest
+    };
+    use crate::{
+        api::msg::{
+            deliver,
+            insert,
+            msg_event,
+            release,
+            Handoff,
+            Limit
+        },
+        config::{
+            DeadLetterConfig,
+            StoreSettings
+        },
+        meta::{
+            Headers,
+            Meta
+        },
+        stores::{
+            ScopedData,
+            Stores
+        }
+    };
+    use msg_store::GetOptions;
+    use std::time::{
+        Duration,
+        Instant
+    };
+
+    fn data() -> Data<AppData> {
+        Data::new(AppData::in_memory(StoreSettings {
+            dead_letter: Some(DeadLetterConfig { max_attempts: 2, priority: Some(0) }),
+            ..StoreSettings::default()
+        }))
+    }
+
+    fn scoped(data: &Data<AppData>) -> ScopedData {
+        let req = TestRequest::default().app_data(Data::new(Stores::new(data.clone(), None))).to_http_request();
+        ScopedData::from_request(&req, &mut Payload::None).into_inner().unwrap()
+    }
+
+    fn info(uuid: Option<&Uuid>) -> Query<Info> {
+        Query(Info { uuid: uuid.map(|uuid| uuid.to_string()) })
+    }
+
+    /// Claims the next message and nacks it, returning the uuid that was claimed if any.
+    fn claim_and_nack(data: &AppData) -> Option<Uuid> {
+        let mut store = data.store.lock().unwrap();
+        let limit = Limit { count: 1, max_bytes: None };
+        let delivery = deliver(data, &mut store, &GetOptions::default(), &Headers::new(), None, Handoff::Claim(Duration::from_secs(60)), limit).unwrap().pop()?;
+        let token = delivery.claim.unwrap().token;
+        let lease = data.leases.lock().unwrap().release(&token, Instant::now()).unwrap();
+        release(data, &mut store, &lease.uuid, None).unwrap();
+        Some(delivery.uuid)
+    }
+
+    fn priority(data: &AppData, uuid: &Uuid) -> Option<u32> {
+        msg_event(&data.store.lock().unwrap(), uuid).map(|msg| msg.priority)
+    }
+
+    fn add(data: &AppData, priority: u32) -> Uuid {
+        insert(data, &mut data.store.lock().unwrap(), priority, "msg".to_string(), Meta::default()).unwrap()
+    }
+
+    #[test]
+    fn message_is_dead_lettered_after_max_attempts() {
+        let data = data();
+        let uuid = add(&data, 1);
+        assert_eq!(claim_and_nack(&data), Some(uuid));
+        assert!(!data.meta.lock().unwrap().is_dead(&uuid));
+        assert_eq!(priority(&data, &uuid), Some(1));
+        assert_eq!(claim_and_nack(&data), Some(uuid));
+        assert!(data.meta.lock().unwrap().is_dead(&uuid));
+        assert_eq!(priority(&data, &uuid), Some(0));
+        assert_eq!(claim_and_nack(&data), None);
+    }
+
+    #[test]
+    fn requeue_returns_a_dead_letter_with_fresh_attempts() {
+        let data = data();
+        let uuid = add(&data, 1);
+        claim_and_nack(&data);
+        claim_and_nack(&data);
+        let response = put::update(scoped(&data), info(Some(&uuid)));
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!data.meta.lock().unwrap().is_dead(&uuid));
+        assert_eq!(priority(&data, &uuid), Some(1));
+        assert_eq!(data.leases.lock().unwrap().attempts(&uuid, None), 0);
+        assert_eq!(claim_and_nack(&data), Some(uuid));
+        assert!(!data.meta.lock().unwrap().is_dead(&uuid));
+        let response = put::update(scoped(&data), Query(Info { uuid: Some("not a uuid".to_string()) }));
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn delete_only_removes_dead_letters() {
+        let data = data();
+        let dead = add(&data, 1);
+        claim_and_nack(&data);
+        claim_and_nack(&data);
+        let alive = add(&data, 1);
+        let response = delete::delete(scoped(&data), info(Some(&alive)));
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(priority(&data, &alive), Some(1));
+        let response = delete::delete(scoped(&data), info(None));
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(priority(&data, &dead), None);
+        assert!(!data.meta.lock().unwrap().is_dead(&dead));
+        assert_eq!(priority(&data, &alive), Some(1));
+    }
+}
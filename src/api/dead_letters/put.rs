@@ -0,0 +1,66 @@
+use actix_web::{
+    HttpResponse,
+    web::{
+        Query
+    }
+};
+use crate::{
+    api::{
+        dead_letters::{
+            Info,
+            selected
+        },
+        msg::requeue
+    },
+    stores::ScopedData
+};
+use msg_store::errors::Error;
+use serde::{
+    Deserialize, 
+    Serialize
+};
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum Reply {
+    Ok { requeued: Vec<String> },
+    Err { code: u32, message: String }
+}
+
+/// Puts dead letters back in their original priority with a fresh count of delivery attempts.
+///
+/// Requeues the dead letter given by uuid, or all of them if none is given.
+pub fn update(data: ScopedData, info: Query<Info>) -> HttpResponse {
+    let mut store = match data.store.try_lock() {
+        Ok(store) => store,
+        Err(_error) => {
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let uuids = match selected(&data, &info) {
+        Some(Ok(uuids)) => uuids,
+        Some(Err(())) => {
+            return HttpResponse::InternalServerError().finish();
+        },
+        None => {
+            return HttpResponse::BadRequest().finish();
+        }
+    };
+    let mut requeued = vec![];
+    for uuid in uuids {
+        match requeue(&data, &mut store, &uuid) {
+            Ok(true) => requeued.push(uuid.to_string()),
+            Ok(false) => (),
+            Err(Error::ExceedesStoreMax) | Err(Error::ExceedesGroupMax) | Err(Error::LacksPriority) => {
+                return HttpResponse::Conflict().json(Reply::Err {
+                    code: 3,
+                    message: format!("The original priority could not take back message {}", uuid.to_string())
+                });
+            },
+            Err(_error) => {
+                return HttpResponse::InternalServerError().finish();
+            }
+        }
+    }
+    HttpResponse::Ok().json(Reply::Ok { requeued })
+}
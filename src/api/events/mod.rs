@@ -0,0 +1,146 @@
+use actix_web::{
+    HttpResponse,
+    rt::time::interval,
+    web::{
+        Bytes
+    }
+};
+use crate::{
+    events::{
+        Event,
+        EventKind,
+        MsgEvent
+    },
+    stores::ScopedData
+};
+use futures::{
+    future::ready,
+    stream::{
+        once,
+        select
+    },
+    StreamExt
+};
+use serde::{
+    Deserialize,
+    Serialize
+};
+use serde_json::to_string;
+use std::time::Duration;
+
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum EventData {
+    #[serde(rename_all = "camelCase")]
+    Insert { uuid: String, priority: u32, byte_size: u32, timestamp: u64 },
+    #[serde(rename_all = "camelCase")]
+    Delete { uuid: String, priority: u32, byte_size: u32, timestamp: u64 },
+    #[serde(rename_all = "camelCase")]
+    Prune { uuid: String, priority: u32, byte_size: u32, timestamp: u64 },
+    #[serde(rename_all = "camelCase")]
+    Export { uuid: String, priority: u32, byte_size: u32, timestamp: u64 },
+    #[serde(rename_all = "camelCase")]
+    Release { uuid: String, priority: u32, byte_size: u32, timestamp: u64 },
+    #[serde(rename_all = "camelCase")]
+    Expire { uuid: String, priority: u32, byte_size: u32, timestamp: u64 },
+    #[serde(rename_all = "camelCase")]
+    Due { uuid: String, priority: u32, byte_size: u32, timestamp: u64 },
+    #[serde(rename_all = "camelCase")]
+    DeadLetter { uuid: String, priority: u32, byte_size: u32, timestamp: u64 },
+    #[serde(rename_all = "camelCase")]
+    Requeue { uuid: String, priority: u32, byte_size: u32, timestamp: u64 },
+    #[serde(rename_all = "camelCase")]
+    Move { uuid: String, priority: u32, previous_priority: u32, byte_size: u32, timestamp: u64 },
+    #[serde(rename_all = "camelCase")]
+    GroupDefaultsUpdate { priority: u32, max_byte_size: Option<u32>, timestamp: u64 },
+    #[serde(rename_all = "camelCase")]
+    GroupDefaultsDelete { priority: u32, timestamp: u64 },
+    #[serde(rename_all = "camelCase")]
+    StoreUpdate { max_byte_size: Option<u32>, timestamp: u64 }
+}
+
+impl EventData {
+    pub fn from_event(event: Event) -> (&'static str, EventData) {
+        let timestamp = event.timestamp;
+        let msg = |msg: MsgEvent| (msg.uuid.to_string(), msg.priority, msg.byte_size);
+        match event.kind {
+            EventKind::Inserted(inserted) => {
+                let (uuid, priority, byte_size) = msg(inserted);
+                ("insert", EventData::Insert { uuid, priority, byte_size, timestamp })
+            },
+            EventKind::Deleted(deleted) => {
+                let (uuid, priority, byte_size) = msg(deleted);
+                ("delete", EventData::Delete { uuid, priority, byte_size, timestamp })
+            },
+            EventKind::Pruned(pruned) => {
+                let (uuid, priority, byte_size) = msg(pruned);
+                ("prune", EventData::Prune { uuid, priority, byte_size, timestamp })
+            },
+            EventKind::Exported(exported) => {
+                let (uuid, priority, byte_size) = msg(exported);
+                ("export", EventData::Export { uuid, priority, byte_size, timestamp })
+            },
+            EventKind::Released(released) => {
+                let (uuid, priority, byte_size) = msg(released);
+                ("release", EventData::Release { uuid, priority, byte_size, timestamp })
+            },
+            EventKind::Expired(expired) => {
+                let (uuid, priority, byte_size) = msg(expired);
+                ("expire", EventData::Expire { uuid, priority, byte_size, timestamp })
+            },
+            EventKind::Due(due) => {
+                let (uuid, priority, byte_size) = msg(due);
+                ("due", EventData::Due { uuid, priority, byte_size, timestamp })
+            },
+            EventKind::DeadLettered(dead_lettered) => {
+                let (uuid, priority, byte_size) = msg(dead_lettered);
+                ("deadLetter", EventData::DeadLetter { uuid, priority, byte_size, timestamp })
+            },
+            EventKind::Requeued(requeued) => {
+                let (uuid, priority, byte_size) = msg(requeued);
+                ("requeue", EventData::Requeue { uuid, priority, byte_size, timestamp })
+            },
+            EventKind::Moved { msg: moved, previous_priority } => {
+                let (uuid, priority, byte_size) = msg(moved);
+                ("move", EventData::Move { uuid, priority, previous_priority, byte_size, timestamp })
+            },
+            EventKind::GroupDefaultsUpdated { priority, max_byte_size } => {
+                ("groupDefaultsUpdate", EventData::GroupDefaultsUpdate { priority, max_byte_size, timestamp })
+            },
+            EventKind::GroupDefaultsDeleted { priority } => {
+                ("groupDefaultsDelete", EventData::GroupDefaultsDelete { priority, timestamp })
+            },
+            EventKind::StoreUpdated { max_byte_size } => {
+                ("storeUpdate", EventData::StoreUpdate { max_byte_size, timestamp })
+            }
+        }
+    }
+}
+
+/// Formats an event as a server-sent event frame.
+fn to_frame(event: Event) -> Bytes {
+    let (name, event_data) = EventData::from_event(event);
+    match to_string(&event_data) {
+        Ok(json) => Bytes::from(format!("event: {}\ndata: {}\n\n", name, json)),
+        Err(_error) => Bytes::new()
+    }
+}
+
+/// Streams store lifecycle events to the client as server-sent events.
+///
+/// A comment line is written periodically so idle connections are not dropped by proxies.
+/// A client that falls too far behind is dropped by the store and its response ends.
+pub fn get(data: ScopedData) -> HttpResponse {
+    let events = data.events.subscribe().map(|event| Some(to_frame(event))).chain(once(ready(None)));
+    let keep_alive = interval(KEEP_ALIVE_INTERVAL).map(|_instant| Some(Bytes::from_static(b":\n\n")));
+    let stream = select(events, keep_alive)
+        .take_while(|frame| ready(frame.is_some()))
+        .filter_map(ready)
+        .map(Ok::<Bytes, actix_web::Error>);
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .header("Cache-Control", "no-cache")
+        .streaming(Box::pin(stream))
+}
@@ -1,15 +1,31 @@
 use actix_web::{
     HttpResponse,
-    web::{Data, Query}
+    web::Query
 };
-use crate::AppData;
-use msg_store::{GetOptions, Uuid};
+use crate::{
+    api::msg::{
+        read,
+        remove
+    },
+    events::EventKind,
+    meta::{
+        Headers,
+        is_false,
+        msg_value
+    },
+    stores::ScopedData
+};
+use msg_store::Uuid;
 use serde::{
     Deserialize, 
     Serialize
 };
-use serde_json::to_string;
+use serde_json::{
+    to_string,
+    Value
+};
 use std::{
+    collections::BTreeMap,
     fs::OpenOptions,
     io::Write,
     ops::Bound::Included,
@@ -20,7 +36,11 @@ use std::{
 #[derive(Debug, Deserialize, Serialize)]
 pub struct StoredPacket {
     pub uuid: String,
-    pub msg: String
+    pub msg: Value,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: Headers,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub binary: bool
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -32,7 +52,7 @@ pub struct Info {
     range_end: Option<u32>
 }
 
-pub fn get(data: Data<AppData>, info: Query<Info>) -> HttpResponse {    
+pub fn get(data: ScopedData, info: Query<Info>) -> HttpResponse {    
 
     let list = {
 
@@ -117,22 +137,24 @@ pub fn get(data: Data<AppData>, info: Query<Info>) -> HttpResponse {
             }
         };
 
-        let msg_option = match store.get(GetOptions::default().uuid(uuid)) {
+        let msg_option = match read(&data, &mut store, &uuid) {
             Ok(msg) => msg,
             Err(_error) => {
                 return HttpResponse::InternalServerError().finish();
             }
         };
 
-        let stored_packet = if let Some(stored_packet) = msg_option {
-            stored_packet
+        let (meta, msg) = if let Some(msg) = msg_option {
+            msg
         } else {
             continue;
         };
 
         let transformed_stored_packet = StoredPacket {
-            uuid: stored_packet.uuid.to_string(),
-            msg: stored_packet.msg
+            uuid: uuid.to_string(),
+            msg: msg_value(&meta, msg),
+            headers: meta.headers,
+            binary: meta.binary
         };
 
         
@@ -148,7 +170,7 @@ pub fn get(data: Data<AppData>, info: Query<Info>) -> HttpResponse {
             return HttpResponse::InternalServerError().finish();
         };
 
-        if let Err(_error) = store.del(&uuid) {
+        if let Err(_error) = remove(&data, &mut store, &uuid, EventKind::Exported) {
             return HttpResponse::InternalServerError().finish();
         }
 
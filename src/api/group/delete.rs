@@ -1,11 +1,14 @@
 use actix_web::{
     HttpResponse,
     web::{
-        Data,
         Query
     }
 };
-use crate::AppData;
+use crate::{
+    api::msg::remove,
+    events::EventKind,
+    stores::ScopedData
+};
 use msg_store::Uuid;
 use serde::{
     Deserialize, 
@@ -17,7 +20,7 @@ pub struct Info {
     priority: u32
 }
 
-pub fn delete(data: Data<AppData>, info: Query<Info>) -> HttpResponse {
+pub fn delete(data: ScopedData, info: Query<Info>) -> HttpResponse {
     let list = {
         let store = match data.store.try_lock() {
             Ok(store) => store,
@@ -42,7 +45,7 @@ pub fn delete(data: Data<AppData>, info: Query<Info>) -> HttpResponse {
                 return HttpResponse::InternalServerError().finish();
             }
         };
-        if let Err(_error) = store.del(uuid) {
+        if let Err(_error) = remove(&data, &mut store, uuid, EventKind::Deleted) {
             return HttpResponse::InternalServerError().finish();
         }
     }
@@ -1,11 +1,10 @@
 use actix_web::{
     HttpResponse,
     web::{
-        Data,
         Query
     }
 };
-use crate::AppData;
+use crate::stores::ScopedData;
 use serde::{
     Deserialize, 
     Serialize
@@ -30,6 +29,8 @@ pub struct Msg {
 pub struct Group {
     priority: u32,
     byte_size: u32,
+    /// The room the messages take up in the database, the byte limits apply to `byte_size`
+    stored_byte_size: u32,
     max_byte_size: Option<u32>,
     msg_count: u32,
     messages: Vec<Msg>
@@ -49,18 +50,25 @@ pub enum Reply {
     OkMany { data: Vec<Group> }
 }
 
-pub fn get(data: Data<AppData>, info: Query<Info>) -> HttpResponse {
+pub fn get(data: ScopedData, info: Query<Info>) -> HttpResponse {
     let store = match data.store.try_lock() {
         Ok(store) => store,
         Err(_error) => {
             return HttpResponse::InternalServerError().finish();
         }
     };
+    let meta = match data.meta.try_lock() {
+        Ok(meta) => meta,
+        Err(_error) => {
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
     if let Some(priority) = info.priority {
         if let Some(group) = store.groups_map.get(&priority) {
             let group = Group {
                 priority: priority.clone(),
                 byte_size: group.byte_size,
+                stored_byte_size: meta.stored_byte_size(&store, Some(priority)),
                 max_byte_size: group.max_byte_size,
                 msg_count: group.msgs_map.len() as u32,
                 messages: match info.include_msg_data {
@@ -82,6 +90,7 @@ pub fn get(data: Data<AppData>, info: Query<Info>) -> HttpResponse {
             Group {
                 priority: priority.clone(),
                 byte_size: group.byte_size,
+                stored_byte_size: meta.stored_byte_size(&store, Some(*priority)),
                 max_byte_size: group.max_byte_size,
                 msg_count: group.msgs_map.len() as u32,
                 messages: match info.include_msg_data {
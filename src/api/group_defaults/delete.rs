@@ -1,11 +1,10 @@
 use actix_web::{
     HttpResponse,
     web::{
-        Data,
         Query
     }
 };
-use crate::{api::update_config, AppData, config::GroupConfig};
+use crate::{api::update_config, config::GroupConfig, events::EventKind, stores::ScopedData};
 
 use serde::{
     Deserialize, 
@@ -50,7 +49,7 @@ impl Reply {
 }
 
 
-pub fn delete(data: Data<AppData>, info: Query<Info>) -> HttpResponse {
+pub fn delete(data: ScopedData, info: Query<Info>) -> HttpResponse {
     let mut store = match data.store.try_lock() {
         Ok(store) => store,
         Err(_error) => {
@@ -58,6 +57,7 @@ pub fn delete(data: Data<AppData>, info: Query<Info>) -> HttpResponse {
         }
     };
     store.delete_group_defaults(info.priority);
+    data.events.publish(EventKind::GroupDefaultsDeleted { priority: info.priority });
     let mut config = match data.config.try_lock() {
         Ok(config) => config,
         Err(_error) => {
@@ -78,7 +78,7 @@ pub fn delete(data: Data<AppData>, info: Query<Info>) -> HttpResponse {
         }
     }).map(|group| group.clone()).collect();
     config.groups = Some(new_groups);
-    if let Err(_error) = update_config(&config, &data.config_location) {
+    if let Err(_error) = update_config(&data, &config) {
         return HttpResponse::InternalServerError().json(Reply::update_error());
     }
     HttpResponse::Ok().finish()
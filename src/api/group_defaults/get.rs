@@ -1,11 +1,16 @@
 use actix_web::{
     HttpResponse,
     web::{
-        Data,
         Query
     }
 };
-use crate::AppData;
+use crate::{
+    config::{
+        CompressionConfig,
+        GroupConfig
+    },
+    stores::ScopedData
+};
 use serde::{
     Deserialize, 
     Serialize
@@ -16,11 +21,13 @@ pub struct Info {
     priority: Option<u32>
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone, Copy)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct GroupDefaults {
     priority: u32,
-    max_byte_size: Option<u32>
+    max_byte_size: Option<u32>,
+    ttl: Option<String>,
+    compression: Option<CompressionConfig>
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -30,18 +37,31 @@ pub enum Reply {
     OkMany { data: Vec<GroupDefaults> }
 }
 
-pub fn get(data: Data<AppData>, info: Query<Info>) -> HttpResponse {
+pub fn get(data: ScopedData, info: Query<Info>) -> HttpResponse {
     let store = match data.store.try_lock() {
         Ok(store) => store,
         Err(_error) => {
             return HttpResponse::InternalServerError().finish();
         }
     };
+    let config = match data.config.try_lock() {
+        Ok(config) => config,
+        Err(_error) => {
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let group_config = |priority: u32| -> Option<&GroupConfig> {
+        config.groups.as_ref()?.iter().find(|group| group.priority == priority)
+    };
+    let ttl = |priority: u32| group_config(priority).and_then(|group| group.ttl.clone());
+    let compression = |priority: u32| group_config(priority).and_then(|group| group.compression);
     if let Some(priority) = info.priority {
         if let Some(defaults) = store.group_defaults.get(&priority) {
             let group_defaults = GroupDefaults {
                 priority: priority.clone(),
-                max_byte_size: defaults.max_byte_size
+                max_byte_size: defaults.max_byte_size,
+                ttl: ttl(priority),
+                compression: compression(priority)
             };
             HttpResponse::Ok().json(Reply::Ok{data: Some(group_defaults)})
         } else {
@@ -51,7 +71,9 @@ pub fn get(data: Data<AppData>, info: Query<Info>) -> HttpResponse {
         let data = store.group_defaults.iter().map(|(priority, defaults)| {
             GroupDefaults {
                 priority: priority.clone(),
-                max_byte_size: defaults.max_byte_size
+                max_byte_size: defaults.max_byte_size,
+                ttl: ttl(*priority),
+                compression: compression(*priority)
             }
         }).collect::<Vec<GroupDefaults>>();
         HttpResponse::Ok().json(Reply::OkMany{data})
@@ -1,14 +1,21 @@
 use actix_web::{
     HttpResponse,
     web::{
-        Data,
         Json
     }
 };
 use crate::{
-    api::update_config,
-    AppData,
-    config::GroupConfig
+    api::{
+        msg::PruneWatch,
+        parse_duration,
+        update_config
+    },
+    config::{
+        CompressionConfig,
+        GroupConfig
+    },
+    events::EventKind,
+    stores::ScopedData
 };
 use msg_store::store::{
     GroupDefaults
@@ -23,10 +30,15 @@ use std::borrow::BorrowMut;
 #[serde(rename_all = "camelCase")]
 pub struct Body {
     priority: u32,
-    max_byte_size: Option<u32>
+    max_byte_size: Option<u32>,
+    ttl: Option<String>,
+    compression: Option<CompressionConfig>
 }
 
-pub fn post(data: Data<AppData>, body: Json<Body>) -> HttpResponse {
+pub fn post(data: ScopedData, body: Json<Body>) -> HttpResponse {
+    if body.ttl.as_deref().is_some_and(|ttl| parse_duration(ttl).is_none()) {
+        return HttpResponse::BadRequest().finish();
+    }
     let mut store = match data.store.try_lock() {
         Ok(store) => store,
         Err(_error) => {
@@ -44,14 +56,19 @@ pub fn post(data: Data<AppData>, body: Json<Body>) -> HttpResponse {
         max_byte_size: body.max_byte_size
     };
 
+    let prune_watch = PruneWatch::new(&store, body.priority..=body.priority);
     if let Err(_error) = store.update_group_defaults(body.priority, &defaults) {
         return HttpResponse::InternalServerError().finish();
     }
+    prune_watch.publish(&data, &store);
+    data.events.publish(EventKind::GroupDefaultsUpdated { priority: body.priority, max_byte_size: body.max_byte_size });
 
     let mk_group_config = || -> GroupConfig {
         GroupConfig {
             priority: body.priority,
-            max_byte_size: body.max_byte_size
+            max_byte_size: body.max_byte_size,
+            ttl: body.ttl.clone(),
+            compression: body.compression
         }
     };
 
@@ -72,6 +89,8 @@ pub fn post(data: Data<AppData>, body: Json<Body>) -> HttpResponse {
         if let Some(index) = group_index {
             if let Some(group) = groups.get_mut(index) {
                 group.max_byte_size = body.max_byte_size;
+                group.ttl = body.ttl.clone();
+                group.compression = body.compression;
             } else {
                 groups.push(mk_group_config());
             }
@@ -85,7 +104,7 @@ pub fn post(data: Data<AppData>, body: Json<Body>) -> HttpResponse {
         ]);
     }
 
-    if let Err(_error) = update_config(&config, &data.config_location) {
+    if let Err(_error) = update_config(&data, &config) {
         return HttpResponse::InternalServerError().finish();
     }
 
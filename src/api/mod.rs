@@ -1,14 +1,43 @@
+pub mod ack;
+pub mod dead_letters;
+pub mod events;
 pub mod export;
 pub mod group;
 pub mod group_defaults;
+pub mod nack;
 pub mod stats;
 pub mod store;
+pub mod stores;
 pub mod msg;
+pub mod msgs;
+pub mod ws;
 
-use crate::config::StoreConfig;
-use std::path::PathBuf;
+use crate::{
+    AppData,
+    config::StoreSettings
+};
+use msg_store::Uuid;
+use std::{
+    collections::BTreeMap,
+    time::Duration
+};
 
-pub fn update_config(config: &StoreConfig, config_path: &Option<PathBuf>) -> Result<(), String> {
+/// Writes the settings of the store to its section of the config file.
+pub fn update_config(data: &AppData, settings: &StoreSettings) -> Result<(), String> {
+    let mut config = match data.config_file.config.try_lock() {
+        Ok(config) => config,
+        Err(error) => {
+            return Err(error.to_string());
+        }
+    };
+    match &data.name {
+        Some(name) => {
+            config.stores.get_or_insert_with(BTreeMap::new).insert(name.clone(), settings.clone());
+        },
+        None => {
+            config.settings = settings.clone();
+        }
+    }
     let should_update = {
         let mut should_update = true;
         if let Some(no_update) = config.no_update {
@@ -19,9 +48,35 @@ pub fn update_config(config: &StoreConfig, config_path: &Option<PathBuf>) -> Res
         should_update
     };
     if should_update {
-        if let Some(config_path) = config_path {
+        if let Some(config_path) = &data.config_file.location {
             config.update_config_file(&config_path)?;
         }        
     }
     Ok(())
 }
+
+/// Parses a uuid string without panicking on malformed input.
+pub fn parse_uuid(uuid: &str) -> Option<Uuid> {
+    let mut split_str = uuid.split('-');
+    let timestamp = split_str.next()?.parse().ok()?;
+    let sequence = split_str.next()?.parse().ok()?;
+    if split_str.next().is_some() {
+        return None;
+    }
+    Some(Uuid { timestamp, sequence })
+}
+
+/// Parses durations such as `500ms`, `30s`, `5m` or `1h`. A bare number is read as seconds.
+pub fn parse_duration(duration: &str) -> Option<Duration> {
+    let duration = duration.trim();
+    let split_at = duration.find(|c: char| !c.is_ascii_digit()).unwrap_or(duration.len());
+    let (amount, unit) = duration.split_at(split_at);
+    let amount: u64 = amount.parse().ok()?;
+    match unit {
+        "ms" => Some(Duration::from_millis(amount)),
+        "" | "s" => Some(Duration::from_secs(amount)),
+        "m" => Some(Duration::from_secs(amount.checked_mul(60)?)),
+        "h" => Some(Duration::from_secs(amount.checked_mul(60 * 60)?)),
+        _ => None
+    }
+}
@@ -1,12 +1,17 @@
 use actix_web::{
     HttpResponse,
     web::{
-        Data,
         Query
     }
 };
-use crate::AppData;
-use msg_store::Uuid;
+use crate::{
+    api::{
+        msg::remove,
+        parse_uuid
+    },
+    events::EventKind,
+    stores::ScopedData
+};
 use serde::{
     Deserialize, 
     Serialize
@@ -17,14 +22,20 @@ pub struct Info {
     uuid: String
 }
 
-pub fn delete(data: Data<AppData>, info: Query<Info>) -> HttpResponse {
+pub fn delete(data: ScopedData, info: Query<Info>) -> HttpResponse {
+    let uuid = match parse_uuid(&info.uuid) {
+        Some(uuid) => uuid,
+        None => {
+            return HttpResponse::BadRequest().finish();
+        }
+    };
     let mut store = match data.store.try_lock() {
         Ok(store) => store,
         Err(_error) => {
             return HttpResponse::InternalServerError().finish();
         }
     };
-    if let Err(_error) = store.del(&Uuid::from_string(&info.uuid)) {
+    if let Err(_error) = remove(&data, &mut store, &uuid, EventKind::Deleted) {
         HttpResponse::InternalServerError().finish()
     } else {
         HttpResponse::Ok().finish()
@@ -1,72 +1,289 @@
 use actix_web::{
     HttpResponse,
+    rt::time::{
+        delay_for,
+        timeout
+    },
+    HttpRequest,
     web::{
-        Data,
         Query
     }
 };
-use crate::AppData;
-use msg_store::{
-    GetOptions,
-    Uuid
+use crate::{
+    api::{
+        msg::{
+            deliver,
+            Delivery,
+            Handoff,
+            Limit,
+            valid_consumer_group
+        },
+        parse_duration,
+        parse_uuid
+    },
+    AppData,
+    events::{
+        Event,
+        EventKind
+    },
+    meta::{
+        Headers,
+        is_false,
+        msg_value
+    },
+    stores::ScopedData
 };
+use futures::{
+    channel::mpsc::Receiver,
+    StreamExt
+};
+use msg_store::GetOptions;
 use serde::{
     Deserialize, 
     Serialize
 };
+use serde_json::Value;
+use std::{
+    collections::BTreeMap,
+    time::{
+        Duration,
+        Instant
+    }
+};
+
+const BUSY_RETRY_INTERVAL: Duration = Duration::from_millis(10);
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct MsgData {
-    uuid: String,
-    msg: String
+    pub uuid: String,
+    pub msg: Value,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: Headers,
+    /// The message is raw bytes, base64 encoded
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub binary: bool
 }
 
 #[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct Info {
     uuid: Option<String>,
     priority: Option<u32>,
-    reverse: Option<bool>
+    reverse: Option<bool>,
+    wait: Option<String>,
+    claim: Option<String>,
+    remove: Option<bool>,
+    limit: Option<u32>,
+    max_bytes: Option<u32>,
+    consumer_group: Option<String>
+}
+
+/// A claimed message along with the lease token needed to ack or nack it.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClaimData {
+    pub uuid: String,
+    pub msg: Value,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: Headers,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub binary: bool,
+    pub lease: String,
+    pub attempts: u32
+}
+
+impl MsgData {
+    pub fn from_delivery(delivery: Delivery) -> MsgData {
+        MsgData {
+            uuid: delivery.uuid.to_string(),
+            msg: msg_value(&delivery.meta, delivery.msg),
+            headers: delivery.meta.headers,
+            binary: delivery.meta.binary
+        }
+    }
+}
+
+impl ClaimData {
+    /// Returns None if the message was not claimed.
+    pub fn from_delivery(delivery: Delivery) -> Option<ClaimData> {
+        let claim = delivery.claim?;
+        Some(ClaimData {
+            uuid: delivery.uuid.to_string(),
+            msg: msg_value(&delivery.meta, delivery.msg),
+            headers: delivery.meta.headers,
+            binary: delivery.meta.binary,
+            lease: claim.token.to_string(),
+            attempts: claim.attempts
+        })
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(untagged)]
 pub enum Reply {
-    Ok { data: Option<MsgData> }
+    Ok { data: Option<MsgData> },
+    Claimed { data: Option<ClaimData> },
+    Batch { data: Vec<MsgData> },
+    ClaimedBatch { data: Vec<ClaimData> }
 }
 
-pub fn get(data: Data<AppData>, info: Query<Info>) -> HttpResponse {
-    let mut store = match data.store.try_lock() {
-        Ok(store) => store,
-        Err(_error) => {
-            return HttpResponse::InternalServerError().finish();
+impl Reply {
+    /// A single message is returned as an object, or `null` if there was none.
+    /// Batches are always returned as an array.
+    fn new(deliveries: Vec<Delivery>, handoff: Handoff, batch: bool) -> Reply {
+        let claimed = matches!(handoff, Handoff::Claim(_));
+        let mut deliveries = deliveries.into_iter();
+        match (claimed, batch) {
+            (false, false) => Reply::Ok { data: deliveries.next().map(MsgData::from_delivery) },
+            (true, false) => Reply::Claimed { data: deliveries.next().and_then(ClaimData::from_delivery) },
+            (false, true) => Reply::Batch { data: deliveries.map(MsgData::from_delivery).collect() },
+            (true, true) => Reply::ClaimedBatch { data: deliveries.filter_map(ClaimData::from_delivery).collect() }
+        }
+    }
+}
+
+/// Waits for the next message to arrive that could satisfy the get options,
+/// either by being inserted, by being moved into the wanted priority, by having its lease end,
+/// by its delay running out or by being requeued.
+///
+/// Returns false once the event stream has closed.
+async fn next_arrival(events: &mut Receiver<Event>, options: &GetOptions) -> bool {
+    while let Some(event) = events.next().await {
+        let arrived = match event.kind {
+            EventKind::Inserted(msg) => msg,
+            EventKind::Moved { msg, .. } => msg,
+            EventKind::Released(msg) => msg,
+            EventKind::Due(msg) => msg,
+            EventKind::Requeued(msg) => msg,
+            _ => continue
+        };
+        let uuid_matches = options.uuid.is_none_or(|wanted| wanted == arrived.uuid);
+        let priority_matches = options.priority.is_none_or(|wanted| wanted == arrived.priority);
+        if uuid_matches && priority_matches {
+            return true;
         }
-    };
-    let mut options = GetOptions::default();
-    if let Some(uuid_string) = info.uuid.clone() {
-        let uuid = Uuid::from_string(&uuid_string);
-        options.uuid = Some(uuid);
     }
-    if let Some(priority) = info.priority {
-        options.priority = Some(priority);
+    false
+}
+/// Reads the headers given as `header.<name>=<value>` query parameters.
+pub fn header_params(req: &HttpRequest) -> Option<Headers> {
+    let params = Query::<Vec<(String, String)>>::from_query(req.query_string()).ok()?;
+    let headers = params.into_inner().into_iter()
+        .filter_map(|(param, value)| param.strip_prefix("header.").map(|name| (name.to_string(), value)))
+        .collect();
+    Some(headers)
+}
+
+/// A get request with its options checked.
+pub struct Request {
+    options: GetOptions,
+    headers: Headers,
+    pub consumer_group: Option<String>,
+    pub handoff: Handoff,
+    limit: Limit,
+    pub batch: bool,
+    wait: Option<Duration>
+}
+
+impl Request {
+    /// Returns None if any of the options is not valid.
+    pub fn new(req: &HttpRequest, info: &Info) -> Option<Request> {
+        let headers = header_params(req)?;
+        let wait = match &info.wait {
+            Some(wait) => Some(parse_duration(wait)?),
+            None => None
+        };
+        let handoff = match (&info.claim, info.remove.unwrap_or(false)) {
+            (Some(_claim), true) => {
+                return None;
+            },
+            (Some(claim), false) => Handoff::Claim(parse_duration(claim)?),
+            (None, true) => Handoff::Remove,
+            (None, false) => Handoff::Peek
+        };
+        let batch = info.limit.is_some() || info.max_bytes.is_some();
+        let limit = Limit {
+            count: info.limit.unwrap_or(if batch { u32::MAX } else { 1 }),
+            max_bytes: info.max_bytes
+        };
+        if limit.count == 0 {
+            return None;
+        }
+        let mut options = GetOptions::default();
+        if let Some(uuid_string) = &info.uuid {
+            options.uuid = Some(parse_uuid(uuid_string)?);
+        }
+        if let Some(priority) = info.priority {
+            options.priority = Some(priority);
+        }
+        if let Some(reverse) = info.reverse {
+            if reverse {
+                options.reverse = true;
+            }
+        }
+        Some(Request { options, headers, consumer_group: info.consumer_group.clone(), handoff, limit, batch, wait })
     }
-    if let Some(reverse) = info.reverse {
-        if reverse {
-            options.reverse = true;
+}
+
+/// Hands out the messages the request asks for, waiting for them to arrive if it allows.
+///
+/// Returns None if the store could not be read.
+pub async fn fetch(data: &AppData, request: &Request) -> Option<Vec<Delivery>> {
+    // listen before the first look at the store so an insert in between is not missed
+    let mut events = request.wait.map(|_wait| data.events.subscribe());
+    let deadline = request.wait.map(|wait| Instant::now() + wait);
+    let mut woken = false;
+    loop {
+        let deliveries = {
+            let mut store = match data.store.try_lock() {
+                Ok(store) => store,
+                Err(_error) => {
+                    if woken && deadline.is_some_and(|deadline| Instant::now() < deadline) {
+                        // the inserting request may still be holding the store
+                        delay_for(BUSY_RETRY_INTERVAL).await;
+                        continue;
+                    }
+                    return None;
+                }
+            };
+            deliver(data, &mut store, &request.options, &request.headers, request.consumer_group.as_deref(), request.handoff, request.limit).ok()?
+        };
+        if !deliveries.is_empty() {
+            return Some(deliveries);
+        }
+        let (events, deadline) = match (events.as_mut(), deadline) {
+            (Some(events), Some(deadline)) => (events, deadline),
+            _ => break
+        };
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        match timeout(remaining, next_arrival(events, &request.options)).await {
+            Ok(true) => {
+                woken = true;
+            },
+            _ => break
         }
     }
-    let stored_packet = match store.get(options) {
-        Ok(data) => data,
+    Some(vec![])
+}
+
+pub async fn get(req: HttpRequest, data: ScopedData, info: Query<Info>) -> HttpResponse {
+    let request = match Request::new(&req, &info) {
+        Some(request) => request,
+        None => {
+            return HttpResponse::BadRequest().finish();
+        }
+    };
+    match valid_consumer_group(&data, request.consumer_group.as_deref(), request.handoff) {
+        Ok(true) => (),
+        Ok(false) => {
+            return HttpResponse::BadRequest().finish();
+        },
         Err(_error) => {
             return HttpResponse::InternalServerError().finish();
         }
-    };
-    let data = if let Some(stored_packet) = stored_packet {
-        Some(MsgData {
-            uuid: stored_packet.uuid.to_string(),
-            msg: stored_packet.msg
-        })
-    } else {
-        None
-    };
-    HttpResponse::Ok().json(Reply::Ok{ data })
-}
\ No newline at end of file
+    }
+    match fetch(&data, &request).await {
+        Some(deliveries) => HttpResponse::Ok().json(Reply::new(deliveries, request.handoff, request.batch)),
+        None => HttpResponse::InternalServerError().finish()
+    }
+}
@@ -1,3 +1,963 @@
 pub mod delete;
 pub mod get;
 pub mod post;
+pub mod put;
+pub mod raw;
+
+use crate::{
+    api::parse_duration,
+    AppData,
+    compression::{
+        compress,
+        decompress
+    },
+    config::{
+        AgingConfig,
+        CompressionConfig
+    },
+    events::{
+        EventKind,
+        MsgEvent
+    },
+    init::Store,
+    leases::Token,
+    meta::{
+        byte_size,
+        decode,
+        encode,
+        now_millis,
+        DeadLetter,
+        Headers,
+        Meta
+    },
+    schedule::Schedule
+};
+#[cfg(feature = "level")]
+use crate::encryption::decrypt;
+use msg_store::{
+    errors::{
+        DbError,
+        Error
+    },
+    store::Group,
+    GetOptions,
+    Keeper,
+    Package,
+    Packet,
+    Uuid
+};
+use std::{
+    collections::{
+        BTreeMap,
+        BTreeSet
+    },
+    ops::RangeInclusive,
+    time::{
+        Duration,
+        Instant,
+        SystemTime,
+        UNIX_EPOCH
+    }
+};
+
+/// Looks up the priority and byte size of a stored message.
+pub fn msg_event(store: &Store, uuid: &Uuid) -> Option<MsgEvent> {
+    let priority = *store.id_to_group_map.get(uuid)?;
+    let byte_size = *store.groups_map.get(&priority)?.msgs_map.get(uuid)?;
+    Some(MsgEvent { uuid: *uuid, priority, byte_size })
+}
+
+/// A snapshot of the messages an operation might prune.
+///
+/// The store does not report which messages it prunes, so the snapshot is compared
+/// against the store once the operation is done and the missing messages are published as pruned.
+pub struct PruneWatch {
+    candidates: Vec<MsgEvent>
+}
+
+impl PruneWatch {
+    pub fn new(store: &Store, priorities: RangeInclusive<u32>) -> PruneWatch {
+        let mut candidates = vec![];
+        for (priority, group) in store.groups_map.range(priorities) {
+            for (uuid, byte_size) in group.msgs_map.iter() {
+                candidates.push(MsgEvent { uuid: *uuid, priority: *priority, byte_size: *byte_size });
+            }
+        }
+        PruneWatch { candidates }
+    }
+
+    pub fn none() -> PruneWatch {
+        PruneWatch { candidates: vec![] }
+    }
+
+    pub fn publish(self, data: &AppData, store: &Store) {
+        for candidate in self.candidates {
+            if !store.id_to_group_map.contains_key(&candidate.uuid) {
+                let _ = forget(data, &candidate.uuid);
+                data.events.publish(EventKind::Pruned(candidate));
+            }
+        }
+    }
+}
+
+/// Only takes a snapshot when adding a message of the given size would go over a limit,
+/// and then only of the messages the store could prune to make room.
+///
+/// The store prunes the oldest messages of the lowest groups up to the new message's priority first,
+/// and stops as soon as enough bytes are free. So only the head of each of those groups is watched,
+/// just long enough to cover the bytes that have to go, rather than every message in them.
+fn watch_insert(store: &Store, priority: u32, byte_size: u32) -> PruneWatch {
+    let store_excess = match store.max_byte_size {
+        Some(max_byte_size) => store.byte_size.saturating_add(byte_size).saturating_sub(max_byte_size),
+        None => 0
+    };
+    let group_excess = match store.groups_map.get(&priority).and_then(|group| group.max_byte_size.map(|max_byte_size| (group, max_byte_size))) {
+        Some((group, max_byte_size)) => group.byte_size.saturating_add(byte_size).saturating_sub(max_byte_size),
+        None => 0
+    };
+    if store_excess == 0 && group_excess == 0 {
+        return PruneWatch::none();
+    }
+    let mut candidates = vec![];
+    // what the store still has to free once the lower groups have been pruned
+    let mut store_left = store_excess;
+    for (group_priority, group) in store.groups_map.range(..=priority) {
+        let mut group_left = if *group_priority == priority {
+            store_left.saturating_add(group_excess)
+        } else {
+            store_left
+        };
+        for (uuid, msg_byte_size) in group.msgs_map.iter() {
+            if group_left == 0 {
+                break;
+            }
+            candidates.push(MsgEvent { uuid: *uuid, priority: *group_priority, byte_size: *msg_byte_size });
+            group_left = group_left.saturating_sub(*msg_byte_size);
+            if *group_priority != priority {
+                store_left = store_left.saturating_sub(*msg_byte_size);
+            }
+        }
+    }
+    PruneWatch { candidates }
+}
+
+/// Where a batch left off in each group.
+///
+/// Every message a batch passes over is hidden or already handed out, and stays that way for the rest
+/// of the batch, so each group only has to be walked once however many messages the batch takes.
+#[derive(Debug, Default)]
+pub struct Cursor {
+    positions: BTreeMap<u32, Uuid>
+}
+
+/// The first message of a group that is not hidden, oldest first unless reversed,
+/// starting from where the cursor left off in the group.
+fn first_visible(group: &Group, priority: u32, reverse: bool, cursor: &mut Cursor, hidden: &impl Fn(&Uuid) -> bool) -> Option<(Uuid, u32)> {
+    let visible = |(uuid, _byte_size): &(&Uuid, &u32)| !hidden(uuid);
+    let next = match (cursor.positions.get(&priority), reverse) {
+        (Some(position), false) => group.msgs_map.range(position..).find(visible),
+        (Some(position), true) => group.msgs_map.range(..=position).rev().find(visible),
+        (None, false) => group.msgs_map.iter().find(visible),
+        (None, true) => group.msgs_map.iter().rev().find(visible)
+    };
+    // with nothing left to find, the group's last message is all the next look has to pass
+    let position = match next {
+        Some((uuid, _byte_size)) => Some(uuid),
+        None if reverse => group.msgs_map.keys().next(),
+        None => group.msgs_map.keys().next_back()
+    };
+    if let Some(position) = position {
+        cursor.positions.insert(priority, *position);
+    }
+    next.map(|(uuid, byte_size)| (*uuid, *byte_size))
+}
+
+/// How much messages that have waited are favoured over the priority they were stored with.
+#[derive(Debug, Clone, Copy)]
+pub struct Aging {
+    /// Nanoseconds a message waits for each priority level it gains
+    interval: u128,
+    max_boost: u32,
+    /// Nanoseconds since the unix epoch, the same clock uuids are made from
+    now: u128
+}
+
+impl Aging {
+    /// Checks the aging settings of a store, which are rejected rather than turning aging off.
+    pub fn new(config: &AgingConfig) -> Result<Aging, String> {
+        let interval = parse_duration(&config.interval)
+            .filter(|interval| !interval.is_zero())
+            .ok_or_else(|| "Aging interval must be a duration such as 30s".to_string())?;
+        Ok(Aging { interval: interval.as_nanos(), max_boost: config.max_boost, now: 0 })
+    }
+
+    /// The priority a message is handed out by, raised by one level for every interval it has waited.
+    fn effective_priority(&self, msg: &MsgEvent) -> u64 {
+        let waited = self.now.saturating_sub(msg.uuid.timestamp);
+        let boost = (waited / self.interval).min(self.max_boost as u128) as u64;
+        msg.priority as u64 + boost
+    }
+}
+
+/// The order messages are handed out in when a consumer does not ask for a priority.
+pub enum Order {
+    /// Highest priority first, the same as `Store::get`
+    Strict,
+    Aging(Aging),
+    /// A copy of the store's schedule, written back once the messages are handed out
+    Weighted(Schedule)
+}
+
+/// The order the store is set up to hand out messages in.
+///
+/// Weights take the place of the strict order, aging only applies without them.
+fn order(data: &AppData) -> Result<Order, Error> {
+    let schedule = data.schedule.try_lock().map_err(|_error| Error::SyncError)?;
+    if schedule.is_weighted() {
+        return Ok(Order::Weighted(schedule.clone()));
+    }
+    let aging = match data.aging {
+        Some(aging) => aging,
+        None => {
+            return Ok(Order::Strict);
+        }
+    };
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|since| since.as_nanos()).unwrap_or(0);
+    Ok(Order::Aging(Aging { now, ..aging }))
+}
+
+/// Finds the next message to hand to a consumer in the same order `Store::get` uses,
+/// passing over the messages that are hidden from consumers.
+///
+/// With aging, the first message of each group is weighed by its effective priority instead,
+/// and the older message wins a tie. With weights, the first message of the weighted group
+/// next in the schedule is taken, or of the highest unweighted group once the weighted ones are empty.
+/// Neither applies to reversed gets.
+/// A message asked for by uuid is found as long as it is in the store.
+pub fn next_msg(store: &Store, options: &GetOptions, order: &Order, cursor: &mut Cursor, hidden: impl Fn(&Uuid) -> bool) -> Option<MsgEvent> {
+    if let Some(uuid) = options.uuid {
+        return msg_event(store, &uuid);
+    }
+    if let Some(priority) = options.priority {
+        let (uuid, byte_size) = first_visible(store.groups_map.get(&priority)?, priority, options.reverse, cursor, &hidden)?;
+        return Some(MsgEvent { uuid, priority, byte_size });
+    }
+    let mut find = |(priority, group): (&u32, &Group)| {
+        first_visible(group, *priority, options.reverse, cursor, &hidden).map(|(uuid, byte_size)| MsgEvent { uuid, priority: *priority, byte_size })
+    };
+    if options.reverse {
+        return store.groups_map.iter().find_map(find);
+    }
+    let aging = match order {
+        Order::Strict => {
+            return store.groups_map.iter().rev().find_map(find);
+        },
+        Order::Aging(aging) => aging,
+        Order::Weighted(schedule) => {
+            return next_weighted(store, schedule, find);
+        }
+    };
+    let mut best: Option<(u64, MsgEvent)> = None;
+    for (priority, group) in store.groups_map.iter().rev() {
+        // the groups left can at most reach their own priority plus the cap
+        if best.as_ref().is_some_and(|(best_priority, _msg)| (*priority as u64) + (aging.max_boost as u64) < *best_priority) {
+            break;
+        }
+        let next = match find((priority, group)) {
+            Some(next) => next,
+            None => continue
+        };
+        let effective_priority = aging.effective_priority(&next);
+        let better = match &best {
+            Some((best_priority, best_msg)) => effective_priority > *best_priority || (effective_priority == *best_priority && next.uuid < best_msg.uuid),
+            None => true
+        };
+        if better {
+            best = Some((effective_priority, next));
+        }
+    }
+    best.map(|(_effective_priority, msg)| msg)
+}
+
+/// The first visible message of the weighted group with the lowest pass,
+/// falling back to the unweighted groups in strict order.
+fn next_weighted<'a>(store: &'a Store, schedule: &Schedule, mut find: impl FnMut((&'a u32, &'a Group)) -> Option<MsgEvent>) -> Option<MsgEvent> {
+    let mut best: Option<(u128, MsgEvent)> = None;
+    for (priority, group) in store.groups_map.iter().rev() {
+        let pass = match schedule.pass(*priority) {
+            Some(pass) => pass,
+            None => continue
+        };
+        // groups are visited from the highest priority down, so a tie keeps the higher priority
+        if best.as_ref().is_some_and(|(best_pass, _msg)| *best_pass <= pass) {
+            continue;
+        }
+        if let Some(next) = find((priority, group)) {
+            best = Some((pass, next));
+        }
+    }
+    match best {
+        Some((_pass, msg)) => Some(msg),
+        None => store.groups_map.iter().rev()
+            .filter(|(priority, _group)| schedule.pass(**priority).is_none())
+            .find_map(find)
+    }
+}
+
+/// What happens to a message once it is handed to a consumer.
+#[derive(Debug, Clone, Copy)]
+pub enum Handoff {
+    /// The message stays in the store for anyone to get
+    Peek,
+    /// The message stays in the store, but claimed messages and dead letters are skipped
+    /// even when they are asked for by uuid
+    Push,
+    /// The message is hidden from other consumers for the visibility timeout
+    Claim(Duration),
+    /// The message is deleted from the store
+    Remove
+}
+
+/// The lease a consumer received along with a claimed message.
+pub struct Claim {
+    pub token: Token,
+    pub attempts: u32
+}
+
+/// A message handed to a consumer.
+pub struct Delivery {
+    pub uuid: Uuid,
+    pub msg: String,
+    pub meta: Meta,
+    pub claim: Option<Claim>
+}
+
+/// How many messages a consumer takes at once.
+#[derive(Debug, Clone, Copy)]
+pub struct Limit {
+    pub count: u32,
+    /// The most bytes the messages may add up to, the first message is always handed out
+    /// so a message bigger than the budget cannot hold up the queue
+    pub max_bytes: Option<u32>
+}
+
+/// The most messages one get hands out unless the store sets `max_batch_size`
+pub const DEFAULT_MAX_BATCH_SIZE: u32 = 1000;
+/// The most bytes one get hands out unless the store sets `max_batch_bytes`
+pub const DEFAULT_MAX_BATCH_BYTES: u32 = 16 * 1024 * 1024;
+
+/// Caps what a consumer asks for at the store's batch limits.
+fn capped(data: &AppData, limit: Limit) -> Result<Limit, Error> {
+    let config = data.config.try_lock().map_err(|_error| Error::SyncError)?;
+    let max_count = config.max_batch_size.unwrap_or(DEFAULT_MAX_BATCH_SIZE);
+    let max_bytes = config.max_batch_bytes.unwrap_or(DEFAULT_MAX_BATCH_BYTES);
+    Ok(Limit {
+        count: limit.count.min(max_count),
+        max_bytes: Some(limit.max_bytes.map_or(max_bytes, |wanted| wanted.min(max_bytes)))
+    })
+}
+
+/// The consumer groups the store fans its messages out to, none if it does not fan out.
+fn consumer_groups(data: &AppData) -> Result<Vec<String>, Error> {
+    let config = data.config.try_lock().map_err(|_error| Error::SyncError)?;
+    Ok(config.consumer_groups.clone().unwrap_or_default())
+}
+
+/// Whether a consumer may get messages as the consumer group.
+///
+/// A store that fans out needs one of its consumer groups to claim or remove messages,
+/// a store that does not takes no consumer group at all.
+pub fn valid_consumer_group(data: &AppData, consumer_group: Option<&str>, handoff: Handoff) -> Result<bool, Error> {
+    let consumer_groups = consumer_groups(data)?;
+    Ok(match consumer_group {
+        Some(consumer_group) => consumer_groups.iter().any(|name| name == consumer_group),
+        None => consumer_groups.is_empty() || matches!(handoff, Handoff::Peek | Handoff::Push)
+    })
+}
+
+/// Gets the next messages for a consumer in store order, skipping messages other consumers have claimed,
+/// dead letters and messages that are delayed, unless they are asked for by uuid.
+/// Only messages that carry every header of the filter are handed out.
+///
+/// A claimed message is hidden from everyone else until the lease is acked, nacked or runs out.
+/// A removed message is deleted while the store is still locked, so no other consumer can get it in between.
+/// With a consumer group, messages the group has acked are skipped, claims only hide a message from
+/// the rest of the group and removing a message acks it for the group.
+pub fn deliver(data: &AppData, store: &mut Store, options: &GetOptions, headers: &Headers, consumer_group: Option<&str>, handoff: Handoff, limit: Limit) -> Result<Vec<Delivery>, Error> {
+    let limit = capped(data, limit)?;
+    let mut deliveries = vec![];
+    let mut delivered = BTreeSet::new();
+    let mut cursor = Cursor::default();
+    let mut byte_size: u32 = 0;
+    let mut order = order(data)?;
+    while deliveries.len() < limit.count as usize {
+        let next = {
+            let leases = data.leases.try_lock().map_err(|_error| Error::SyncError)?;
+            let meta = data.meta.try_lock().map_err(|_error| Error::SyncError)?;
+            let now = Instant::now();
+            let now_millis = now_millis();
+            let unavailable = |uuid: &Uuid| delivered.contains(uuid)
+                || meta.is_expired(uuid, now_millis)
+                || !meta.matches(uuid, headers)
+                || meta.is_acked(uuid, consumer_group);
+            let taken = |uuid: &Uuid| leases.is_claimed(uuid, consumer_group, now) || meta.is_dead(uuid);
+            let hidden = |uuid: &Uuid| unavailable(uuid) || taken(uuid) || meta.is_delayed(uuid, now_millis);
+            match next_msg(store, options, &order, &mut cursor, hidden) {
+                Some(next) if !unavailable(&next.uuid) && (matches!(handoff, Handoff::Peek) || !taken(&next.uuid)) => next,
+                _ => break
+            }
+        };
+        if limit.max_bytes.is_some_and(|max_bytes| !deliveries.is_empty() && byte_size.saturating_add(next.byte_size) > max_bytes) {
+            break;
+        }
+        let (meta, msg) = read(data, store, &next.uuid)?.ok_or(Error::SyncError)?;
+        let claim = match handoff {
+            Handoff::Peek | Handoff::Push => None,
+            Handoff::Claim(visibility_timeout) => {
+                let token = Token::new().map_err(|_error| Error::DbError(DbError("Could not generate a lease token".to_string())))?;
+                let attempts = data.leases.try_lock().map_err(|_error| Error::SyncError)?.claim(next.uuid, consumer_group, token, visibility_timeout);
+                Some(Claim { token, attempts })
+            },
+            Handoff::Remove => {
+                acknowledge(data, store, &next.uuid, consumer_group)?;
+                None
+            }
+        };
+        if let Order::Weighted(schedule) = &mut order {
+            schedule.charge(next.priority, next.byte_size);
+        }
+        byte_size = byte_size.saturating_add(next.byte_size);
+        delivered.insert(next.uuid);
+        deliveries.push(Delivery { uuid: next.uuid, msg, meta, claim });
+    }
+    // peeks and pushes leave the schedule where it was, so the same messages are seen until someone takes them
+    if let (Order::Weighted(schedule), false) = (order, matches!(handoff, Handoff::Peek | Handoff::Push)) {
+        *data.schedule.try_lock().map_err(|_error| Error::SyncError)? = schedule;
+    }
+    Ok(deliveries)
+}
+
+/// Adds a stored body to the store, counting only the given byte size toward the limits.
+///
+/// The store sizes messages by their body, so a body carrying meta data is added with a
+/// stand in of the right size first and then written over in the database.
+fn add_body(store: &mut Store, priority: u32, byte_size: u32, body: String) -> Result<Uuid, Error> {
+    if body.len() as u32 == byte_size {
+        return store.add(Packet::new(priority, body));
+    }
+    let uuid = store.add(Packet::new(priority, " ".repeat(byte_size as usize)))?;
+    store.db.add(&Package { uuid, priority, msg: body, byte_size }).map_err(Error::DbError)?;
+    Ok(uuid)
+}
+
+/// Works out when a new message expires from either a time to live or a point in time,
+/// in milliseconds since the unix epoch.
+pub fn expires_at(ttl: Option<&str>, expires_at: Option<u64>) -> Result<Option<u64>, String> {
+    match (ttl, expires_at) {
+        (Some(_ttl), Some(_expires_at)) => Err("Only one of ttl and expiresAt can be set".to_string()),
+        (Some(ttl), None) => {
+            let ttl = parse_duration(ttl).ok_or_else(|| "Invalid ttl".to_string())?;
+            Ok(Some(now_millis().saturating_add(ttl.as_millis() as u64)))
+        },
+        (None, expires_at) => Ok(expires_at)
+    }
+}
+
+/// The compression setting of the priority group, or of the store if the group has none.
+fn compression(data: &AppData, priority: u32) -> Result<Option<CompressionConfig>, Error> {
+    let config = data.config.try_lock().map_err(|_error| Error::SyncError)?;
+    let group_compression = config.groups.as_ref()
+        .and_then(|groups| groups.iter().find(|group| group.priority == priority))
+        .and_then(|group| group.compression);
+    Ok(group_compression.or(config.compression))
+}
+
+/// The default time to live of the priority group, as set in the group config.
+fn group_ttl(data: &AppData, priority: u32) -> Result<Option<Duration>, Error> {
+    let config = data.config.try_lock().map_err(|_error| Error::SyncError)?;
+    let ttl = config.groups.as_ref()
+        .and_then(|groups| groups.iter().find(|group| group.priority == priority))
+        .and_then(|group| group.ttl.as_deref())
+        .and_then(parse_duration);
+    Ok(ttl)
+}
+
+/// Adds a message to the store and lets any listeners know it has arrived,
+/// along with any messages that were pruned to make room for it.
+///
+/// Messages without an expiry get the default time to live of their priority group, if it has one.
+pub fn insert(data: &AppData, store: &mut Store, priority: u32, msg: String, mut meta: Meta) -> Result<Uuid, Error> {
+    if meta.expires_at.is_none() {
+        meta.expires_at = group_ttl(data, priority)?.map(|ttl| now_millis().saturating_add(ttl.as_millis() as u64));
+    }
+    let byte_size = byte_size(&meta, &msg);
+    let msg = match compression(data, priority)? {
+        Some(config) => compress(&config, &mut meta, msg),
+        None => msg
+    };
+    #[cfg(feature = "level")]
+    let msg = match &data.cipher {
+        Some(cipher) => {
+            meta.encrypted = true;
+            cipher.encrypt(&msg)?
+        },
+        None => msg
+    };
+    let body = encode(&meta, msg);
+    let stored_size = body.len() as u32;
+    let prune_watch = watch_insert(store, priority, byte_size);
+    let uuid = add_body(store, priority, byte_size, body)?;
+    {
+        let mut meta_index = data.meta.try_lock().map_err(|_error| Error::SyncError)?;
+        meta_index.insert(uuid, meta);
+        meta_index.set_stored_size(uuid, byte_size, stored_size);
+    }
+    prune_watch.publish(data, store);
+    data.events.publish(EventKind::Inserted(MsgEvent { uuid, priority, byte_size }));
+    Ok(uuid)
+}
+
+/// Adds a message unless one was already added with the same idempotency key within the window,
+/// in which case the uuid of that message is returned.
+pub fn insert_once(data: &AppData, store: &mut Store, key: Option<String>, priority: u32, msg: String, meta: Meta) -> Result<Uuid, Error> {
+    let key = match key {
+        Some(key) => key,
+        None => {
+            return insert(data, store, priority, msg, meta);
+        }
+    };
+    if let Some(uuid) = data.idempotency.try_lock().map_err(|_error| Error::SyncError)?.get(&key, now_millis()) {
+        return Ok(uuid);
+    }
+    let uuid = insert(data, store, priority, msg, meta)?;
+    data.idempotency.try_lock().map_err(|_error| Error::SyncError)?
+        .insert(key, uuid, now_millis())
+        .map_err(|error| Error::File(error.to_string()))?;
+    Ok(uuid)
+}
+
+/// Removes a message from the store and lets listeners know why it left.
+///
+/// Returns false if the message was not in the store.
+pub fn remove(data: &AppData, store: &mut Store, uuid: &Uuid, kind: fn(MsgEvent) -> EventKind) -> Result<bool, Error> {
+    let msg_event = match msg_event(store, uuid) {
+        Some(msg_event) => msg_event,
+        None => {
+            return Ok(false);
+        }
+    };
+    store.del(uuid)?;
+    forget(data, uuid)?;
+    data.events.publish(kind(msg_event));
+    Ok(true)
+}
+
+/// Settles a message a consumer is done with.
+///
+/// Without a consumer group the message is deleted. With one it is marked as acked by the group
+/// and only deleted once every consumer group of the store has acked it.
+/// Returns false if the message was not in the store.
+pub fn acknowledge(data: &AppData, store: &mut Store, uuid: &Uuid, consumer_group: Option<&str>) -> Result<bool, Error> {
+    let consumer_group = match consumer_group {
+        Some(consumer_group) => consumer_group,
+        None => {
+            return remove(data, store, uuid, EventKind::Deleted);
+        }
+    };
+    let msg = match msg_event(store, uuid) {
+        Some(msg) => msg,
+        None => {
+            return Ok(false);
+        }
+    };
+    let mut meta = data.meta.try_lock().map_err(|_error| Error::SyncError)?.get(uuid);
+    meta.acked_by.insert(consumer_group.to_string());
+    if consumer_groups(data)?.iter().all(|name| meta.acked_by.contains(name)) {
+        return remove(data, store, uuid, EventKind::Deleted);
+    }
+    rewrite_meta(data, store, &msg, meta)?;
+    Ok(true)
+}
+
+/// Deletes a message that has outlived its time to live.
+///
+/// It is counted as expired rather than deleted.
+pub fn expire(data: &AppData, store: &mut Store, uuid: &Uuid) -> Result<bool, Error> {
+    if !remove(data, store, uuid, EventKind::Expired)? {
+        return Ok(false);
+    }
+    store.msgs_deleted = store.msgs_deleted.saturating_sub(1);
+    data.meta.try_lock().map_err(|_error| Error::SyncError)?.inc_msgs_expired();
+    Ok(true)
+}
+
+/// Drops everything the server keeps about a message besides the message itself.
+fn forget(data: &AppData, uuid: &Uuid) -> Result<(), Error> {
+    data.leases.try_lock().map_err(|_error| Error::SyncError)?.forget(uuid);
+    data.meta.try_lock().map_err(|_error| Error::SyncError)?.forget(uuid);
+    Ok(())
+}
+
+/// Reads a message from the store, split from the meta data it is stored with, decrypted and decompressed.
+#[cfg_attr(not(feature = "level"), allow(unused_variables))]
+pub fn read(data: &AppData, store: &mut Store, uuid: &Uuid) -> Result<Option<(Meta, String)>, Error> {
+    let stored_packet = match store.get(GetOptions::default().uuid(*uuid))? {
+        Some(stored_packet) => stored_packet,
+        None => {
+            return Ok(None);
+        }
+    };
+    let (meta, msg) = decode(stored_packet.msg);
+    #[cfg(feature = "level")]
+    let msg = decrypt(data.cipher.as_ref(), &meta, msg)?;
+    let msg = decompress(&meta, msg)?;
+    Ok(Some((meta, msg)))
+}
+
+/// Takes a message out of the store's bookkeeping and database without counting it as deleted.
+fn detach(store: &mut Store, msg: &MsgEvent) -> Result<(), Error> {
+    let group = store.groups_map.get_mut(&msg.priority).ok_or(Error::SyncError)?;
+    group.msgs_map.remove(&msg.uuid);
+    group.byte_size -= msg.byte_size;
+    if group.msgs_map.is_empty() {
+        store.groups_map.remove(&msg.priority);
+    }
+    store.byte_size -= msg.byte_size;
+    store.id_to_group_map.remove(&msg.uuid);
+    store.db.del(&msg.uuid).map_err(Error::DbError)
+}
+
+/// Puts a message back into the store's bookkeeping and database under the given uuid.
+///
+/// No limits are checked, callers must only attach messages the store had room for.
+fn attach(store: &mut Store, msg: &MsgEvent, body: String) -> Result<(), Error> {
+    let package = Package {
+        uuid: msg.uuid,
+        priority: msg.priority,
+        msg: body,
+        byte_size: msg.byte_size
+    };
+    store.db.add(&package).map_err(Error::DbError)?;
+    let max_byte_size = store.group_defaults.get(&msg.priority).and_then(|defaults| defaults.max_byte_size);
+    let group = store.groups_map.entry(msg.priority).or_insert_with(|| Group::new(max_byte_size));
+    group.msgs_map.insert(msg.uuid, msg.byte_size);
+    group.byte_size += msg.byte_size;
+    store.byte_size += msg.byte_size;
+    store.id_to_group_map.insert(msg.uuid, msg.priority);
+    Ok(())
+}
+
+/// Moves a message into another priority group while keeping its uuid.
+///
+/// The message is added to the destination the same way a new message would be, so the
+/// destination's byte limits are checked and lower priority messages are pruned as needed.
+/// If the destination cannot take the message it is left where it was.
+/// Returns false if the message was not in the store.
+pub fn reprioritize(data: &AppData, store: &mut Store, uuid: &Uuid, new_priority: u32) -> Result<bool, Error> {
+    let msg = match msg_event(store, uuid) {
+        Some(msg) => msg,
+        None => {
+            return Ok(false);
+        }
+    };
+    if msg.priority == new_priority {
+        return Ok(true);
+    }
+    let body = store.get(GetOptions::default().uuid(*uuid))?.ok_or(Error::SyncError)?.msg;
+    detach(store, &msg)?;
+    let prune_watch = watch_insert(store, new_priority, msg.byte_size);
+    let temp_uuid = match add_body(store, new_priority, msg.byte_size, body.clone()) {
+        Ok(temp_uuid) => temp_uuid,
+        Err(error) => {
+            attach(store, &msg, body)?;
+            return Err(error);
+        }
+    };
+    // the store hands out a fresh uuid, swap the original back in
+    let moved = MsgEvent { uuid: *uuid, priority: new_priority, byte_size: msg.byte_size };
+    detach(store, &MsgEvent { uuid: temp_uuid, ..moved })?;
+    attach(store, &moved, body)?;
+    store.msgs_inserted = store.msgs_inserted.saturating_sub(1);
+    prune_watch.publish(data, store);
+    data.events.publish(EventKind::Moved { msg: moved, previous_priority: msg.priority });
+    Ok(true)
+}
+
+/// Writes a message back to the database with new meta data, keeping its place in the store.
+fn rewrite_meta(data: &AppData, store: &mut Store, msg: &MsgEvent, meta: Meta) -> Result<(), Error> {
+    let body = store.get(GetOptions::default().uuid(msg.uuid))?.ok_or(Error::SyncError)?.msg;
+    let (_meta, body) = decode(body);
+    let package = Package {
+        uuid: msg.uuid,
+        priority: msg.priority,
+        msg: encode(&meta, body),
+        byte_size: msg.byte_size
+    };
+    store.db.add(&package).map_err(Error::DbError)?;
+    let mut meta_index = data.meta.try_lock().map_err(|_error| Error::SyncError)?;
+    meta_index.insert(msg.uuid, meta);
+    meta_index.set_stored_size(msg.uuid, msg.byte_size, package.msg.len() as u32);
+    Ok(())
+}
+
+/// Puts a message back in line once its lease has ended without an ack, or sets it aside
+/// as a dead letter when it has used up its delivery attempts.
+///
+/// In a store that fans out, a consumer group running out of attempts sets the message aside for every group.
+pub fn release(data: &AppData, store: &mut Store, uuid: &Uuid, consumer_group: Option<&str>) -> Result<(), Error> {
+    let msg = match msg_event(store, uuid) {
+        Some(msg) => msg,
+        None => {
+            return Ok(());
+        }
+    };
+    let dead_letter_config = data.config.try_lock().map_err(|_error| Error::SyncError)?.dead_letter;
+    let attempts = data.leases.try_lock().map_err(|_error| Error::SyncError)?.attempts(uuid, consumer_group);
+    match dead_letter_config {
+        Some(dead_letter_config) if attempts >= dead_letter_config.max_attempts => {
+            dead_letter(data, store, &msg, attempts, dead_letter_config.priority)
+        },
+        _ => {
+            data.events.publish(EventKind::Released(msg));
+            Ok(())
+        }
+    }
+}
+
+/// Sets a message aside so it is no longer handed to consumers.
+///
+/// If the dead letter priority cannot take the message it stays in its own group.
+fn dead_letter(data: &AppData, store: &mut Store, msg: &MsgEvent, attempts: u32, priority: Option<u32>) -> Result<(), Error> {
+    let mut meta = data.meta.try_lock().map_err(|_error| Error::SyncError)?.get(&msg.uuid);
+    meta.dead_letter = Some(DeadLetter { priority: msg.priority, attempts });
+    rewrite_meta(data, store, msg, meta)?;
+    if let Some(priority) = priority {
+        match reprioritize(data, store, &msg.uuid, priority) {
+            Ok(_moved) => (),
+            Err(Error::ExceedesStoreMax) | Err(Error::ExceedesGroupMax) | Err(Error::LacksPriority) => (),
+            Err(error) => {
+                return Err(error);
+            }
+        }
+    }
+    data.leases.try_lock().map_err(|_error| Error::SyncError)?.forget(&msg.uuid);
+    data.meta.try_lock().map_err(|_error| Error::SyncError)?.inc_msgs_dead_lettered();
+    if let Some(msg) = msg_event(store, &msg.uuid) {
+        data.events.publish(EventKind::DeadLettered(msg));
+    }
+    Ok(())
+}
+
+/// Returns a dead letter to the priority it came from with a fresh count of delivery attempts.
+///
+/// Returns false if the message is not a dead letter.
+pub fn requeue(data: &AppData, store: &mut Store, uuid: &Uuid) -> Result<bool, Error> {
+    let mut meta = data.meta.try_lock().map_err(|_error| Error::SyncError)?.get(uuid);
+    let dead_letter = match meta.dead_letter.take() {
+        Some(dead_letter) => dead_letter,
+        None => {
+            return Ok(false);
+        }
+    };
+    if !reprioritize(data, store, uuid, dead_letter.priority)? {
+        return Ok(false);
+    }
+    let msg = msg_event(store, uuid).ok_or(Error::SyncError)?;
+    rewrite_meta(data, store, &msg, meta)?;
+    data.events.publish(EventKind::Requeued(msg));
+    Ok(true)
+}
+
+#[cfg(all(test, feature = "mem"))]
+mod tests {
+    use super::*;
+    use crate::config::{
+        CompressionAlgorithm,
+        FairDequeueConfig,
+        GroupConfig,
+        GroupWeight,
+        StoreSettings
+    };
+
+    fn store(priorities: &[u32]) -> Store {
+        let mut store = msg_store::open();
+        for priority in priorities {
+            store.add(Packet::new(*priority, "msg".to_string())).unwrap();
+        }
+        store
+    }
+
+    fn weighted(weights: &[(u32, u32)]) -> Order {
+        let weights = weights.iter().map(|(priority, weight)| GroupWeight { priority: *priority, weight: *weight }).collect();
+        Order::Weighted(Schedule::new(Some(&FairDequeueConfig { weights, unit: None })))
+    }
+
+    fn next_priority(store: &Store, order: &Order) -> Option<u32> {
+        next_msg(store, &GetOptions::default(), order, &mut Cursor::default(), |_uuid| false).map(|msg| msg.priority)
+    }
+
+    /// Inserts a message of the given size and checks the watch saw every message the store pruned for it.
+    fn assert_watched(store: &mut Store, priority: u32, byte_size: u32) -> usize {
+        let before: Vec<Uuid> = store.id_to_group_map.keys().copied().collect();
+        let watch = watch_insert(store, priority, byte_size);
+        store.add(Packet::new(priority, " ".repeat(byte_size as usize))).unwrap();
+        let pruned: Vec<Uuid> = before.into_iter().filter(|uuid| !store.id_to_group_map.contains_key(uuid)).collect();
+        for uuid in &pruned {
+            assert!(watch.candidates.iter().any(|candidate| candidate.uuid == *uuid));
+        }
+        watch.candidates.len()
+    }
+
+    #[test]
+    fn insert_watch_covers_only_what_can_be_pruned() {
+        let mut store = store(&[1, 1, 1, 2, 2, 3]);
+        assert_eq!(assert_watched(&mut store, 3, 3), 0);
+        store.max_byte_size = Some(21);
+        assert_eq!(assert_watched(&mut store, 3, 5), 2);
+        store.groups_map.get_mut(&3).unwrap().max_byte_size = Some(8);
+        assert_eq!(assert_watched(&mut store, 3, 4), 4);
+        assert_eq!(assert_watched(&mut store, 2, 10), 1);
+    }
+
+    #[test]
+    fn reprioritize_keeps_the_uuid_and_body() {
+        let data = AppData::in_memory(StoreSettings::default());
+        let mut store = data.store.lock().unwrap();
+        let uuid = insert(&data, &mut store, 1, "first".to_string(), Meta::default()).unwrap();
+        let other = insert(&data, &mut store, 1, "second".to_string(), Meta::default()).unwrap();
+        assert!(reprioritize(&data, &mut store, &uuid, 2).unwrap());
+        assert_eq!(msg_event(&store, &uuid).unwrap().priority, 2);
+        assert_eq!(msg_event(&store, &other).unwrap().priority, 1);
+        assert_eq!(read(&data, &mut store, &uuid).unwrap().unwrap().1, "first");
+        assert_eq!(store.msgs_inserted, 2);
+        assert_eq!(store.byte_size, 11);
+        assert!(!reprioritize(&data, &mut store, &Uuid { timestamp: 0, sequence: 0 }, 2).unwrap());
+    }
+
+    #[test]
+    fn reprioritize_leaves_a_message_the_target_cannot_take() {
+        let data = AppData::in_memory(StoreSettings {
+            groups: Some(vec![GroupConfig { priority: 2, max_byte_size: Some(3), ttl: None, compression: None }]),
+            ..StoreSettings::default()
+        });
+        let mut store = data.store.lock().unwrap();
+        let uuid = insert(&data, &mut store, 1, "too big".to_string(), Meta::default()).unwrap();
+        assert!(matches!(reprioritize(&data, &mut store, &uuid, 2), Err(Error::ExceedesGroupMax)));
+        assert_eq!(msg_event(&store, &uuid).unwrap().priority, 1);
+        assert_eq!(read(&data, &mut store, &uuid).unwrap().unwrap().1, "too big");
+        assert!(store.groups_map.get(&2).is_none_or(|group| group.msgs_map.is_empty()));
+        assert_eq!(store.byte_size, 7);
+    }
+
+    #[test]
+    fn reprioritize_keeps_the_lease_and_meta() {
+        let data = AppData::in_memory(StoreSettings::default());
+        let mut store = data.store.lock().unwrap();
+        let headers: Headers = BTreeMap::from([("kind".to_string(), "a".to_string())]);
+        let meta = Meta { headers: headers.clone(), expires_at: Some(u64::MAX), ..Meta::default() };
+        let uuid = insert(&data, &mut store, 1, "msg".to_string(), meta).unwrap();
+        data.leases.lock().unwrap().claim(uuid, None, Token::new().unwrap(), Duration::from_secs(60));
+        assert!(reprioritize(&data, &mut store, &uuid, 2).unwrap());
+        assert!(data.leases.lock().unwrap().is_claimed(&uuid, None, Instant::now()));
+        assert_eq!(data.leases.lock().unwrap().attempts(&uuid, None), 1);
+        let meta = data.meta.lock().unwrap().get(&uuid);
+        assert_eq!(meta.headers, headers);
+        assert_eq!(meta.expires_at, Some(u64::MAX));
+        assert_eq!(read(&data, &mut store, &uuid).unwrap().unwrap().0.headers, headers);
+    }
+
+    #[test]
+    fn gzip_round_trip_counts_the_uncompressed_size() {
+        let compression = CompressionConfig { algorithm: CompressionAlgorithm::Gzip, min_byte_size: Some(16) };
+        let data = AppData::in_memory(StoreSettings { compression: Some(compression), ..StoreSettings::default() });
+        let mut store = data.store.lock().unwrap();
+        let text = "a message that repeats itself ".repeat(20);
+        let uuid = insert(&data, &mut store, 1, text.clone(), Meta::default()).unwrap();
+        let bytes = base64::encode(vec![7u8; 600]);
+        let binary = insert(&data, &mut store, 1, bytes.clone(), Meta { binary: true, ..Meta::default() }).unwrap();
+        let small = insert(&data, &mut store, 1, "short".to_string(), Meta::default()).unwrap();
+
+        let (meta, msg) = read(&data, &mut store, &uuid).unwrap().unwrap();
+        assert_eq!(meta.compressed, Some(CompressionAlgorithm::Gzip));
+        assert_eq!(msg, text);
+        let (meta, msg) = read(&data, &mut store, &binary).unwrap().unwrap();
+        assert_eq!(meta.compressed, Some(CompressionAlgorithm::Gzip));
+        assert_eq!(msg, bytes);
+        let (meta, msg) = read(&data, &mut store, &small).unwrap().unwrap();
+        assert_eq!(meta.compressed, None);
+        assert_eq!(msg, "short");
+
+        // the limits and stats count the message as it was sent, the database holds less
+        assert_eq!(msg_event(&store, &uuid).unwrap().byte_size, 600);
+        assert_eq!(msg_event(&store, &binary).unwrap().byte_size, 600);
+        assert_eq!(store.byte_size, 1205);
+        assert!(data.meta.lock().unwrap().stored_byte_size(&store, None) < 1205);
+    }
+
+    fn peek(data: &AppData, store: &mut Store, options: &GetOptions, count: u32) -> Vec<Uuid> {
+        let limit = Limit { count, max_bytes: None };
+        let deliveries = deliver(data, store, options, &Headers::new(), None, Handoff::Peek, limit).unwrap();
+        deliveries.iter().map(|delivery| delivery.uuid).collect()
+    }
+
+    #[test]
+    fn batch_passes_over_hidden_messages_in_store_order() {
+        let data = AppData::in_memory(StoreSettings::default());
+        let mut store = data.store.lock().unwrap();
+        let low: Vec<Uuid> = (0..3).map(|_msg| insert(&data, &mut store, 1, "low".to_string(), Meta::default()).unwrap()).collect();
+        let high: Vec<Uuid> = (0..3).map(|_msg| insert(&data, &mut store, 2, "high".to_string(), Meta::default()).unwrap()).collect();
+        data.leases.lock().unwrap().claim(high[0], None, Token::new().unwrap(), Duration::from_secs(60));
+        data.leases.lock().unwrap().claim(low[1], None, Token::new().unwrap(), Duration::from_secs(60));
+        assert_eq!(peek(&data, &mut store, &GetOptions::default(), 10), vec![high[1], high[2], low[0], low[2]]);
+        assert_eq!(peek(&data, &mut store, &GetOptions::default().reverse(true), 10), vec![low[2], low[0], high[2], high[1]]);
+        assert_eq!(peek(&data, &mut store, &GetOptions::default().priority(1).reverse(true), 10), vec![low[2], low[0]]);
+    }
+
+    #[test]
+    fn batch_is_capped_at_the_store_limits() {
+        let data = AppData::in_memory(StoreSettings { max_batch_size: Some(2), max_batch_bytes: Some(7), ..StoreSettings::default() });
+        let mut store = data.store.lock().unwrap();
+        let uuids: Vec<Uuid> = (0..4).map(|_msg| insert(&data, &mut store, 1, "msg".to_string(), Meta::default()).unwrap()).collect();
+        assert_eq!(peek(&data, &mut store, &GetOptions::default(), u32::MAX), uuids[..2].to_vec());
+        data.config.lock().unwrap().max_batch_size = None;
+        assert_eq!(peek(&data, &mut store, &GetOptions::default(), u32::MAX).len(), 2);
+        data.config.lock().unwrap().max_batch_bytes = Some(9);
+        assert_eq!(peek(&data, &mut store, &GetOptions::default(), u32::MAX).len(), 3);
+    }
+
+    #[test]
+    fn aging_boosts_by_interval_up_to_the_cap() {
+        let aging = Aging { interval: 10, max_boost: 3, now: 100 };
+        let effective_priority = |timestamp: u128| aging.effective_priority(&MsgEvent {
+            uuid: Uuid { timestamp, sequence: 1 },
+            priority: 1,
+            byte_size: 3
+        });
+        assert_eq!(effective_priority(100), 1);
+        assert_eq!(effective_priority(91), 1);
+        assert_eq!(effective_priority(75), 3);
+        assert_eq!(effective_priority(0), 4);
+        // a message stamped after now has not waited at all
+        assert_eq!(effective_priority(200), 1);
+    }
+
+    #[test]
+    fn aging_leaves_strict_order_without_waiting() {
+        let store = store(&[1, 3, 2]);
+        let aging = Aging { interval: u128::MAX, max_boost: 5, now: 0 };
+        assert_eq!(next_priority(&store, &Order::Aging(aging)), Some(3));
+    }
+
+    #[test]
+    fn weighted_tie_goes_to_higher_priority() {
+        let order = weighted(&[(1, 1), (2, 1), (3, 1)]);
+        assert_eq!(next_priority(&store(&[1, 2, 3]), &order), Some(3));
+    }
+
+    #[test]
+    fn unweighted_groups_wait_for_weighted_ones() {
+        let order = weighted(&[(1, 1), (2, 1)]);
+        assert_eq!(next_priority(&store(&[1, 5]), &order), Some(1));
+        assert_eq!(next_priority(&store(&[4, 5]), &order), Some(5));
+    }
+}
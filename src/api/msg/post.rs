@@ -1,21 +1,63 @@
 use actix_web::{
     HttpResponse,
     web::{
-        Data,
         Json
     }
 };
-use crate::AppData;
-use msg_store::{Packet, errors::Error};
+use crate::{
+    api::msg::{
+        expires_at,
+        insert_once
+    },
+    meta::{
+        Headers,
+        Meta,
+        msg_text
+    },
+    stores::ScopedData
+};
+use msg_store::errors::Error;
 use serde::{
     Deserialize, 
     Serialize
 };
+use serde_json::Value;
+use std::collections::BTreeSet;
 
 #[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct Body {
-    priority: u32,
-    msg: String
+    pub priority: u32,
+    /// Any JSON value, strings are stored as they are
+    pub msg: Value,
+    pub ttl: Option<String>,
+    pub expires_at: Option<u64>,
+    pub not_before: Option<u64>,
+    pub idempotency_key: Option<String>,
+    pub headers: Option<Headers>
+}
+
+impl Body {
+    /// The message as it is stored.
+    pub fn text(&self) -> String {
+        msg_text(&self.msg)
+    }
+
+    /// The meta data the message is stored with, or None if it is not valid.
+    pub fn meta(&self) -> Option<Meta> {
+        let expires_at = expires_at(self.ttl.as_deref(), self.expires_at).ok()?;
+        Some(Meta {
+            expires_at,
+            not_before: self.not_before,
+            dead_letter: None,
+            headers: self.headers.clone().unwrap_or_default(),
+            binary: false,
+            json: !self.msg.is_string(),
+            compressed: None,
+            encrypted: false,
+            acked_by: BTreeSet::new()
+        })
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -36,14 +78,23 @@ impl Reply {
     }
 }
 
-pub fn post(data: Data<AppData>, body: Json<Body>) -> HttpResponse {
+/// Adds a message to the store.
+///
+/// A retry that sends the same `idempotencyKey` within the window gets the uuid of the first insert back.
+pub fn post(data: ScopedData, body: Json<Body>) -> HttpResponse {
+    let meta = match body.meta() {
+        Some(meta) => meta,
+        None => {
+            return HttpResponse::BadRequest().finish();
+        }
+    };
     let mut store = match data.store.try_lock() {
         Ok(store) => store,
         Err(_error) => {
             return HttpResponse::InternalServerError().finish();
         }
     };
-    let uuid = match store.add(Packet::new(body.priority, body.msg.to_string())) {
+    let uuid = match insert_once(&data, &mut store, body.idempotency_key.clone(), body.priority, body.text(), meta) {
         Ok(uuid) => uuid,
         Err(error) => {
             match error {
@@ -0,0 +1,55 @@
+use actix_web::{
+    HttpResponse,
+    web::{
+        Json
+    }
+};
+use crate::{
+    api::{
+        msg::{
+            post::Reply,
+            reprioritize
+        },
+        parse_uuid
+    },
+    stores::ScopedData
+};
+use msg_store::errors::Error;
+use serde::{
+    Deserialize, 
+    Serialize
+};
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Body {
+    uuid: String,
+    new_priority: u32
+}
+
+pub fn update(data: ScopedData, body: Json<Body>) -> HttpResponse {
+    let uuid = match parse_uuid(&body.uuid) {
+        Some(uuid) => uuid,
+        None => {
+            return HttpResponse::BadRequest().finish();
+        }
+    };
+    let mut store = match data.store.try_lock() {
+        Ok(store) => store,
+        Err(_error) => {
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    match reprioritize(&data, &mut store, &uuid, body.new_priority) {
+        Ok(true) => HttpResponse::Ok().json(Reply::Ok { uuid: body.uuid.clone() }),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(error) => {
+            match error {
+                Error::ExceedesStoreMax => HttpResponse::Conflict().json(Reply::exceeds_store_max()),
+                Error::ExceedesGroupMax => HttpResponse::Conflict().json(Reply::exceeds_group_max()),
+                Error::LacksPriority => HttpResponse::Conflict().json(Reply::lacks_priority()),
+                _ => HttpResponse::InternalServerError().finish()
+            }
+        }
+    }
+}
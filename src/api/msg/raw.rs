@@ -0,0 +1,138 @@
+use actix_web::{
+    HttpRequest,
+    HttpResponse,
+    web::{
+        Bytes,
+        Query
+    }
+};
+use crate::{
+    api::msg::{
+        expires_at,
+        get::{
+            fetch,
+            header_params,
+            Info,
+            Request
+        },
+        insert_once,
+        post::Reply,
+        valid_consumer_group
+    },
+    meta::Meta,
+    stores::ScopedData
+};
+use msg_store::errors::Error;
+use serde::{
+    Deserialize,
+    Serialize
+};
+use std::collections::BTreeSet;
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PostInfo {
+    priority: u32,
+    ttl: Option<String>,
+    expires_at: Option<u64>,
+    not_before: Option<u64>,
+    idempotency_key: Option<String>
+}
+
+/// Adds the request body to the store as raw bytes.
+///
+/// Headers are given as `header.<name>` query parameters. Only the size of the bytes counts toward the byte limits.
+pub fn post(req: HttpRequest, data: ScopedData, info: Query<PostInfo>, body: Bytes) -> HttpResponse {
+    let expires_at = match expires_at(info.ttl.as_deref(), info.expires_at) {
+        Ok(expires_at) => expires_at,
+        Err(_error) => {
+            return HttpResponse::BadRequest().finish();
+        }
+    };
+    let headers = match header_params(&req) {
+        Some(headers) => headers,
+        None => {
+            return HttpResponse::BadRequest().finish();
+        }
+    };
+    let meta = Meta {
+        expires_at,
+        not_before: info.not_before,
+        dead_letter: None,
+        headers,
+        binary: true,
+        json: false,
+        compressed: None,
+        encrypted: false,
+        acked_by: BTreeSet::new()
+    };
+    let mut store = match data.store.try_lock() {
+        Ok(store) => store,
+        Err(_error) => {
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let uuid = match insert_once(&data, &mut store, info.idempotency_key.clone(), info.priority, base64::encode(&body), meta) {
+        Ok(uuid) => uuid,
+        Err(error) => {
+            match error {
+                Error::ExceedesStoreMax => { return HttpResponse::Conflict().json(Reply::exceeds_store_max()); },
+                Error::ExceedesGroupMax => { return HttpResponse::Conflict().json(Reply::exceeds_group_max()); },
+                Error::LacksPriority => { return HttpResponse::Conflict().json(Reply::lacks_priority()); },
+                _ => { return HttpResponse::InternalServerError().finish(); }
+            }
+        }
+    };
+    HttpResponse::Ok().json(Reply::Ok { uuid: uuid.to_string() })
+}
+
+/// Gets the next message as raw bytes, with its uuid in the `Msg-Uuid` header.
+///
+/// Takes the same options as a get of a single message and answers `204` if there is none.
+/// A claimed message also has `Msg-Lease` and `Msg-Attempts` headers.
+pub async fn get(req: HttpRequest, data: ScopedData, info: Query<Info>) -> HttpResponse {
+    let request = match Request::new(&req, &info) {
+        Some(request) if !request.batch => request,
+        _ => {
+            return HttpResponse::BadRequest().finish();
+        }
+    };
+    match valid_consumer_group(&data, request.consumer_group.as_deref(), request.handoff) {
+        Ok(true) => (),
+        Ok(false) => {
+            return HttpResponse::BadRequest().finish();
+        },
+        Err(_error) => {
+            return HttpResponse::InternalServerError().finish();
+        }
+    }
+    let delivery = match fetch(&data, &request).await {
+        Some(deliveries) => match deliveries.into_iter().next() {
+            Some(delivery) => delivery,
+            None => {
+                return HttpResponse::NoContent().finish();
+            }
+        },
+        None => {
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let body = if delivery.meta.binary {
+        match base64::decode(&delivery.msg) {
+            Ok(body) => body,
+            Err(_error) => {
+                return HttpResponse::InternalServerError().finish();
+            }
+        }
+    } else {
+        delivery.msg.into_bytes()
+    };
+    let mut response = HttpResponse::Ok();
+    response.content_type("application/octet-stream");
+    response.header("Msg-Uuid", delivery.uuid.to_string());
+    if let Some(claim) = delivery.claim {
+        response.header("Msg-Lease", claim.token.to_string());
+        response.header("Msg-Attempts", claim.attempts.to_string());
+    }
+    response.body(body)
+}
@@ -0,0 +1,62 @@
+use actix_web::{
+    HttpResponse,
+    web::{
+        Json
+    }
+};
+use crate::{
+    api::{
+        msg::remove,
+        msgs::get::Body,
+        parse_uuid
+    },
+    events::EventKind,
+    stores::ScopedData
+};
+use serde::{
+    Deserialize, 
+    Serialize
+};
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "status", rename_all = "camelCase")]
+pub enum DeleteResult {
+    Deleted { uuid: String },
+    Missing { uuid: String }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum Reply {
+    Ok { data: Vec<DeleteResult> }
+}
+
+/// Deletes a list of messages by uuid, reporting each one as deleted or missing in the order asked for.
+pub fn delete(data: ScopedData, body: Json<Body>) -> HttpResponse {
+    let mut uuids = vec![];
+    for uuid_string in body.uuids.iter() {
+        match parse_uuid(uuid_string) {
+            Some(uuid) => uuids.push(uuid),
+            None => {
+                return HttpResponse::BadRequest().finish();
+            }
+        }
+    }
+    let mut store = match data.store.try_lock() {
+        Ok(store) => store,
+        Err(_error) => {
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let mut results = vec![];
+    for uuid in uuids {
+        match remove(&data, &mut store, &uuid, EventKind::Deleted) {
+            Ok(true) => results.push(DeleteResult::Deleted { uuid: uuid.to_string() }),
+            Ok(false) => results.push(DeleteResult::Missing { uuid: uuid.to_string() }),
+            Err(_error) => {
+                return HttpResponse::InternalServerError().finish();
+            }
+        }
+    }
+    HttpResponse::Ok().json(Reply::Ok { data: results })
+}
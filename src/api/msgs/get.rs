@@ -0,0 +1,83 @@
+use actix_web::{
+    HttpResponse,
+    web::{
+        Json
+    }
+};
+use crate::{
+    api::{
+        msg::read,
+        parse_uuid
+    },
+    meta::{
+        Headers,
+        is_false,
+        msg_value
+    },
+    stores::ScopedData
+};
+use serde::{
+    Deserialize, 
+    Serialize
+};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Body {
+    pub uuids: Vec<String>
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "status", rename_all = "camelCase")]
+pub enum MsgResult {
+    Found {
+        uuid: String,
+        msg: Value,
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        headers: Headers,
+        #[serde(default, skip_serializing_if = "is_false")]
+        binary: bool
+    },
+    Missing { uuid: String }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum Reply {
+    Ok { data: Vec<MsgResult> }
+}
+
+/// Looks up a list of messages by uuid, reporting each one as found or missing in the order asked for.
+pub fn get(data: ScopedData, body: Json<Body>) -> HttpResponse {
+    let mut uuids = vec![];
+    for uuid_string in body.uuids.iter() {
+        match parse_uuid(uuid_string) {
+            Some(uuid) => uuids.push(uuid),
+            None => {
+                return HttpResponse::BadRequest().finish();
+            }
+        }
+    }
+    let mut store = match data.store.try_lock() {
+        Ok(store) => store,
+        Err(_error) => {
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let mut results = vec![];
+    for uuid in uuids {
+        if !store.id_to_group_map.contains_key(&uuid) {
+            results.push(MsgResult::Missing { uuid: uuid.to_string() });
+            continue;
+        }
+        match read(&data, &mut store, &uuid) {
+            Ok(Some((meta, msg))) => results.push(MsgResult::Found { uuid: uuid.to_string(), msg: msg_value(&meta, msg), headers: meta.headers, binary: meta.binary }),
+            Ok(None) => results.push(MsgResult::Missing { uuid: uuid.to_string() }),
+            Err(_error) => {
+                return HttpResponse::InternalServerError().finish();
+            }
+        }
+    }
+    HttpResponse::Ok().json(Reply::Ok { data: results })
+}
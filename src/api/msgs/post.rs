@@ -0,0 +1,260 @@
+use actix_web::{
+    HttpResponse,
+    web::{
+        Json
+    }
+};
+use crate::{
+    api::msg::{
+        insert_once,
+        post::{
+            Body,
+            Reply as MsgReply
+        }
+    },
+    AppData,
+    init::Store,
+    meta::now_millis,
+    stores::ScopedData
+};
+use msg_store::{
+    errors::{
+        DbError,
+        Error
+    },
+    store::Group,
+    Keeper,
+    Package,
+    Packet,
+    PacketMetaData,
+    Store as DryStore,
+    Uuid
+};
+use serde::{
+    Deserialize, 
+    Serialize
+};
+use std::collections::BTreeSet;
+
+/// Why a message in the batch could not be stored.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Rejected {
+    index: usize,
+    #[serde(flatten)]
+    reason: MsgReply
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum Reply {
+    Ok { uuids: Vec<String> },
+    Err { errors: Vec<Rejected> }
+}
+
+/// A database that keeps nothing, used to try inserts out on a copy of the store's bookkeeping.
+struct NullDb;
+
+impl Keeper for NullDb {
+    fn add(&mut self, _package: &Package) -> Result<(), DbError> {
+        Ok(())
+    }
+    fn get(&mut self, _uuid: &Uuid) -> Result<Option<String>, DbError> {
+        Ok(None)
+    }
+    fn del(&mut self, _uuid: &Uuid) -> Result<(), DbError> {
+        Ok(())
+    }
+    fn fetch(&mut self) -> Result<Vec<PacketMetaData>, DbError> {
+        Ok(vec![])
+    }
+}
+
+/// Copies the limits and message sizes of the store without any of the message bodies.
+fn dry_store(store: &Store) -> Result<DryStore<NullDb>, Error> {
+    let mut dry_store = DryStore::open(NullDb)?;
+    dry_store.max_byte_size = store.max_byte_size;
+    dry_store.byte_size = store.byte_size;
+    dry_store.group_defaults = store.group_defaults.clone();
+    dry_store.id_to_group_map = store.id_to_group_map.clone();
+    for (priority, group) in store.groups_map.iter() {
+        dry_store.groups_map.insert(*priority, Group {
+            max_byte_size: group.max_byte_size,
+            byte_size: group.byte_size,
+            msgs_map: group.msgs_map.clone()
+        });
+    }
+    Ok(dry_store)
+}
+
+fn reason(error: Error) -> Option<MsgReply> {
+    match error {
+        Error::ExceedesStoreMax => Some(MsgReply::exceeds_store_max()),
+        Error::ExceedesGroupMax => Some(MsgReply::exceeds_group_max()),
+        Error::LacksPriority => Some(MsgReply::lacks_priority()),
+        _ => None
+    }
+}
+
+/// Adds the batch to a copy of the store to find out whether every message would be kept.
+///
+/// A message that fits when it is added but is then pruned to make room for a later
+/// message of the same batch is rejected as well, since it would not end up stored.
+fn try_batch(store: &Store, batch: &[(usize, &Body)]) -> Result<Vec<Rejected>, Error> {
+    let mut dry_store = dry_store(store)?;
+    let mut rejected = vec![];
+    let mut added = vec![];
+    for (index, body) in batch.iter().copied() {
+        match dry_store.add(Packet::new(body.priority, body.text())) {
+            Ok(uuid) => added.push((index, uuid)),
+            Err(error) => rejected.push(Rejected { index, reason: reason(error).ok_or(Error::SyncError)? })
+        }
+    }
+    for (index, uuid) in added {
+        if !dry_store.id_to_group_map.contains_key(&uuid) {
+            rejected.push(Rejected { index, reason: MsgReply::lacks_priority() });
+        }
+    }
+    rejected.sort_by_key(|rejected| rejected.index);
+    Ok(rejected)
+}
+
+/// The messages of the batch that would actually be added, leaving out repeated idempotency keys.
+///
+/// Returns None if the keys could not be checked.
+fn new_msgs<'a>(data: &AppData, batch: &'a [Body]) -> Option<Vec<(usize, &'a Body)>> {
+    let idempotency = data.idempotency.try_lock().ok()?;
+    let now = now_millis();
+    let mut keys = BTreeSet::new();
+    let new_msgs = batch.iter().enumerate()
+        .filter(|(_index, body)| match &body.idempotency_key {
+            Some(key) => idempotency.get(key, now).is_none() && keys.insert(key.as_str()),
+            None => true
+        })
+        .collect();
+    Some(new_msgs)
+}
+
+/// Adds a batch of messages, either all of them or none at all.
+///
+/// The uuids are returned in the order the messages were sent. If any message cannot be
+/// stored nothing is added and the reason is given for each message that did not fit.
+/// Messages with an idempotency key that was already used, in the window or earlier in
+/// the batch, are not added again and get the uuid of the first insert.
+pub fn post(data: ScopedData, body: Json<Vec<Body>>) -> HttpResponse {
+    let mut metas = vec![];
+    for body in body.iter() {
+        match body.meta() {
+            Some(meta) => metas.push(meta),
+            None => {
+                return HttpResponse::BadRequest().finish();
+            }
+        }
+    }
+    let mut store = match data.store.try_lock() {
+        Ok(store) => store,
+        Err(_error) => {
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let new_msgs = match new_msgs(&data, &body) {
+        Some(new_msgs) => new_msgs,
+        None => {
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let rejected = match try_batch(&store, &new_msgs) {
+        Ok(rejected) => rejected,
+        Err(_error) => {
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    if !rejected.is_empty() {
+        return HttpResponse::Conflict().json(Reply::Err { errors: rejected });
+    }
+    let mut uuids = vec![];
+    for (body, meta) in body.into_inner().into_iter().zip(metas) {
+        match insert_once(&data, &mut store, body.idempotency_key.clone(), body.priority, body.text(), meta) {
+            Ok(uuid) => uuids.push(uuid.to_string()),
+            Err(_error) => {
+                return HttpResponse::InternalServerError().finish();
+            }
+        }
+    }
+    HttpResponse::Ok().json(Reply::Ok { uuids })
+}
+
+#[cfg(all(test, feature = "mem"))]
+mod tests {
+    use super::*;
+    use crate::config::{
+        GroupConfig,
+        StoreSettings
+    };
+    use serde_json::{
+        from_value,
+        json
+    };
+
+    fn batch(msgs: serde_json::Value) -> Vec<Body> {
+        from_value(msgs).unwrap()
+    }
+
+    fn codes(rejected: &[Rejected]) -> Vec<(usize, u32)> {
+        rejected.iter().map(|rejected| match &rejected.reason {
+            MsgReply::Err { code, .. } => (rejected.index, *code),
+            MsgReply::Ok { .. } => panic!("a rejected message carries an error")
+        }).collect()
+    }
+
+    #[test]
+    fn batch_reports_every_message_that_does_not_fit_and_stores_nothing() {
+        let data = AppData::in_memory(StoreSettings {
+            max_byte_size: Some(20),
+            groups: Some(vec![GroupConfig { priority: 2, max_byte_size: Some(4), ttl: None, compression: None }]),
+            ..StoreSettings::default()
+        });
+        let store = data.store.lock().unwrap();
+        let body = batch(json!([
+            { "priority": 1, "msg": "fits" },
+            { "priority": 2, "msg": "too big" },
+            { "priority": 1, "msg": "over the store limit" },
+            { "priority": 3, "msg": "fits" },
+            { "priority": 1, "msg": "x".repeat(21) }
+        ]));
+        let new_msgs = new_msgs(&data, &body).unwrap();
+        assert_eq!(codes(&try_batch(&store, &new_msgs).unwrap()), vec![(0, 3), (1, 2), (2, 3), (4, 1)]);
+        assert_eq!(store.byte_size, 0);
+        assert!(store.id_to_group_map.is_empty());
+    }
+
+    #[test]
+    fn batch_rejects_messages_pruned_by_later_ones() {
+        let data = AppData::in_memory(StoreSettings { max_byte_size: Some(10), ..StoreSettings::default() });
+        let store = data.store.lock().unwrap();
+        let body = batch(json!([
+            { "priority": 1, "msg": "first" },
+            { "priority": 2, "msg": "second" },
+            { "priority": 1, "msg": "third" }
+        ]));
+        let new_msgs = new_msgs(&data, &body).unwrap();
+        assert_eq!(codes(&try_batch(&store, &new_msgs).unwrap()), vec![(0, 3), (2, 3)]);
+        assert!(store.id_to_group_map.is_empty());
+    }
+
+    #[test]
+    fn batch_leaves_out_repeated_idempotency_keys() {
+        let data = AppData::in_memory(StoreSettings::default());
+        {
+            let mut store = data.store.lock().unwrap();
+            insert_once(&data, &mut store, Some("used".to_string()), 1, "msg".to_string(), Default::default()).unwrap();
+        }
+        let body = batch(json!([
+            { "priority": 1, "msg": "a", "idempotencyKey": "used" },
+            { "priority": 1, "msg": "b", "idempotencyKey": "new" },
+            { "priority": 1, "msg": "c", "idempotencyKey": "new" },
+            { "priority": 1, "msg": "d" }
+        ]));
+        let indexes: Vec<usize> = new_msgs(&data, &body).unwrap().iter().map(|(index, _body)| *index).collect();
+        assert_eq!(indexes, vec![1, 3]);
+    }
+}
@@ -0,0 +1,56 @@
+use actix_web::{
+    HttpResponse,
+    web::{
+        Json
+    }
+};
+use crate::{
+    api::{
+        ack::{
+            Body,
+            Reply
+        },
+        msg::{
+            msg_event,
+            release
+        }
+    },
+    leases::Token,
+    stores::ScopedData
+};
+use std::time::Instant;
+
+/// Gives a claimed message back before its lease runs out so another consumer can take it.
+///
+/// The message keeps its place in its group and its delivery attempt count,
+/// unless it has used up its attempts and is set aside as a dead letter.
+pub fn post(data: ScopedData, body: Json<Body>) -> HttpResponse {
+    let token = match Token::parse(&body.lease) {
+        Some(token) => token,
+        None => {
+            return HttpResponse::BadRequest().finish();
+        }
+    };
+    let mut store = match data.store.try_lock() {
+        Ok(store) => store,
+        Err(_error) => {
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let released = match data.leases.try_lock() {
+        Ok(mut leases) => leases.release(&token, Instant::now()),
+        Err(_error) => {
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let (msg, lease) = match released.and_then(|lease| Some((msg_event(&store, &lease.uuid)?, lease))) {
+        Some(released) => released,
+        None => {
+            return HttpResponse::NotFound().finish();
+        }
+    };
+    if release(&data, &mut store, &msg.uuid, lease.consumer_group.as_deref()).is_err() {
+        return HttpResponse::InternalServerError().finish();
+    }
+    HttpResponse::Ok().json(Reply::Ok { uuid: msg.uuid.to_string() })
+}
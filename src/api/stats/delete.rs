@@ -1,12 +1,7 @@
-use actix_web::{
-    HttpResponse,
-    web::{
-        Data
-    }
-};
+use actix_web::HttpResponse;
 use crate::{
-    AppData,
-    api::stats::get::Stats
+    api::stats::get::Stats,
+    stores::ScopedData
 };
 
 use serde::{
@@ -20,20 +15,31 @@ pub enum Reply {
     Ok { data: Stats }
 }
 
-pub fn delete(data: Data<AppData>) -> HttpResponse {
+pub fn delete(data: ScopedData) -> HttpResponse {
     let mut store = match data.store.try_lock() {
         Ok(store) => store,
         Err(_error) => {
             return HttpResponse::InternalServerError().finish();
         }
     };
+    let mut meta = match data.meta.try_lock() {
+        Ok(meta) => meta,
+        Err(_error) => {
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
     let data = Stats {
         inserted: store.msgs_inserted,
         deleted: store.msgs_deleted,
-        pruned: store.msgs_pruned
+        pruned: store.msgs_pruned,
+        expired: meta.msgs_expired,
+        dead_lettered: meta.msgs_dead_lettered,
+        dead_letters: meta.dead_letters().len()
     };
     store.msgs_inserted = 0;
     store.msgs_deleted = 0;
     store.msgs_pruned = 0;
+    meta.msgs_expired = 0;
+    meta.msgs_dead_lettered = 0;
     HttpResponse::Ok().json(Reply::Ok { data })
 }
\ No newline at end of file
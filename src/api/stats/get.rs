@@ -1,10 +1,5 @@
-use actix_web::{
-    HttpResponse,
-    web::{
-        Data
-    }
-};
-use crate::AppData;
+use actix_web::HttpResponse;
+use crate::stores::ScopedData;
 
 use serde::{
     Deserialize, 
@@ -29,7 +24,11 @@ pub struct GroupData {
 pub struct Stats {
     pub inserted: u32,
     pub deleted: u32,
-    pub pruned: u32
+    pub pruned: u32,
+    pub expired: u32,
+    pub dead_lettered: u32,
+    /// The number of dead letters currently held
+    pub dead_letters: usize
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -38,16 +37,25 @@ pub enum Reply {
     Ok { data: Stats }
 }
 
-pub fn get(data: Data<AppData>) -> HttpResponse {
+pub fn get(data: ScopedData) -> HttpResponse {
     let store = match data.store.try_lock() {
         Ok(store) => store,
         Err(_error) => {
             return HttpResponse::InternalServerError().finish();
         }
     };
+    let meta = match data.meta.try_lock() {
+        Ok(meta) => meta,
+        Err(_error) => {
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
     HttpResponse::Ok().json(Reply::Ok{ data: Stats {
         inserted: store.msgs_inserted,
         deleted: store.msgs_deleted,
-        pruned: store.msgs_pruned
+        pruned: store.msgs_pruned,
+        expired: meta.msgs_expired,
+        dead_lettered: meta.msgs_dead_lettered,
+        dead_letters: meta.dead_letters().len()
     } })
 }
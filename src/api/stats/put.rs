@@ -1,11 +1,10 @@
 use actix_web::{
     HttpResponse,
     web::{
-        Data,
         Json
     }
 };
-use crate::AppData;
+use crate::stores::ScopedData;
 
 use serde::{
     Deserialize, 
@@ -16,25 +15,33 @@ use serde::{
 pub struct StatsProps {
     pub inserted: Option<u32>,
     pub deleted: Option<u32>,
-    pub pruned: Option<u32>
+    pub pruned: Option<u32>,
+    pub expired: Option<u32>,
+    pub dead_lettered: Option<u32>
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub enum Body {
-    Replace { inserted: Option<u32>, deleted: Option<u32>, pruned: Option<u32> },
-    Add { inserted: Option<u32>, deleted: Option<u32>, pruned: Option<u32> }
+    Replace { inserted: Option<u32>, deleted: Option<u32>, pruned: Option<u32>, expired: Option<u32>, dead_lettered: Option<u32> },
+    Add { inserted: Option<u32>, deleted: Option<u32>, pruned: Option<u32>, expired: Option<u32>, dead_lettered: Option<u32> }
 }
 
-pub fn update(data: Data<AppData>, body: Json<Body>) -> HttpResponse {
+pub fn update(data: ScopedData, body: Json<Body>) -> HttpResponse {
     let mut store = match data.store.try_lock() {
         Ok(store) => store,
         Err(_error) => {
             return HttpResponse::InternalServerError().finish();
         }
     };
+    let mut meta = match data.meta.try_lock() {
+        Ok(meta) => meta,
+        Err(_error) => {
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
     match body.0 {
-        Body::Replace { inserted, deleted, pruned } => {
+        Body::Replace { inserted, deleted, pruned, expired, dead_lettered } => {
             if let Some(inserted) = inserted {
                 store.msgs_inserted = inserted;
             }
@@ -44,8 +51,14 @@ pub fn update(data: Data<AppData>, body: Json<Body>) -> HttpResponse {
             if let Some(pruned) = pruned {
                 store.msgs_pruned = pruned;
             }
+            if let Some(expired) = expired {
+                meta.msgs_expired = expired;
+            }
+            if let Some(dead_lettered) = dead_lettered {
+                meta.msgs_dead_lettered = dead_lettered;
+            }
         },
-        Body::Add { inserted, deleted, pruned } => {
+        Body::Add { inserted, deleted, pruned, expired, dead_lettered } => {
             if let Some(inserted) = inserted {
                 store.msgs_inserted += inserted;
             }
@@ -55,6 +68,12 @@ pub fn update(data: Data<AppData>, body: Json<Body>) -> HttpResponse {
             if let Some(pruned) = pruned {
                 store.msgs_pruned += pruned;
             }
+            if let Some(expired) = expired {
+                meta.msgs_expired += expired;
+            }
+            if let Some(dead_lettered) = dead_lettered {
+                meta.msgs_dead_lettered += dead_lettered;
+            }
         }
     }
 
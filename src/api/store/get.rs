@@ -1,8 +1,5 @@
-use actix_web::{
-    HttpResponse,
-    web::Data
-};
-use crate::AppData;
+use actix_web::HttpResponse;
+use crate::stores::ScopedData;
 
 use serde::{
     Deserialize, 
@@ -19,18 +16,31 @@ pub struct GroupDefaults {
 pub struct GroupData {
     priority: u32,
     byte_size: u32,
+    /// The room the messages take up in the database, the byte limits apply to `byte_size`
+    stored_byte_size: u32,
     max_byte_size: Option<u32>,
     msg_count: usize
 }
 
+/// How far a consumer group has worked through the store
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ConsumerGroupData {
+    name: String,
+    /// The messages in the store the group has not acked yet
+    msg_count: usize
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct StoreData {
     byte_size: u32,
+    stored_byte_size: u32,
     max_byte_size: Option<u32>,
     msg_count: usize,
     group_count: usize,
     groups: Vec<GroupData>,
-    group_defaults: Vec<GroupDefaults>
+    group_defaults: Vec<GroupDefaults>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    consumer_groups: Vec<ConsumerGroupData>
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -39,17 +49,36 @@ pub enum Reply {
     Ok { data: StoreData }
 }
 
-pub fn get(data: Data<AppData>) -> HttpResponse {
+pub fn get(data: ScopedData) -> HttpResponse {
     let store = match data.store.try_lock() {
         Ok(store) => store,
         Err(_error) => {
             return HttpResponse::InternalServerError().finish();
         }
     };
+    let meta = match data.meta.try_lock() {
+        Ok(meta) => meta,
+        Err(_error) => {
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let consumer_groups = match data.config.try_lock() {
+        Ok(config) => config.consumer_groups.clone().unwrap_or_default(),
+        Err(_error) => {
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let consumer_groups = consumer_groups.into_iter().map(|name| {
+        ConsumerGroupData {
+            msg_count: store.id_to_group_map.len().saturating_sub(meta.acked_count(&name)),
+            name
+        }
+    }).collect::<Vec<ConsumerGroupData>>();
     let groups = store.groups_map.iter().map(|(priority, group)| {
         GroupData {
             priority: *priority,
             byte_size: group.byte_size,
+            stored_byte_size: meta.stored_byte_size(&store, Some(*priority)),
             max_byte_size: group.max_byte_size,
             msg_count: group.msgs_map.len()
         }
@@ -62,11 +91,13 @@ pub fn get(data: Data<AppData>) -> HttpResponse {
     }).collect::<Vec<GroupDefaults>>();
     let data = StoreData {
         byte_size: store.byte_size,
+        stored_byte_size: meta.stored_byte_size(&store, None),
         max_byte_size: store.max_byte_size,
         msg_count: store.id_to_group_map.len(),
         group_count: store.groups_map.len(),
         groups,
-        group_defaults
+        group_defaults,
+        consumer_groups
     };
     HttpResponse::Ok().json(Reply::Ok{ data })
 }
@@ -1,13 +1,16 @@
 use actix_web::{
     HttpResponse,
     web::{
-        Data,
         Json
     }
 };
 use crate::{
-    api::update_config,
-    AppData
+    api::{
+        msg::PruneWatch,
+        update_config
+    },
+    events::EventKind,
+    stores::ScopedData
 };
 use msg_store::store::StoreDefaults;
 
@@ -21,7 +24,7 @@ pub struct Body {
     max_byte_size: Option<u32>
 }
 
-pub fn update(data: Data<AppData>, body: Json<Body>) -> HttpResponse {
+pub fn update(data: ScopedData, body: Json<Body>) -> HttpResponse {
     let mut store = match data.store.try_lock() {
         Ok(store) => store,
         Err(_error) => {
@@ -36,11 +39,17 @@ pub fn update(data: Data<AppData>, body: Json<Body>) -> HttpResponse {
     };
     store.max_byte_size = body.max_byte_size;
     let defaults = StoreDefaults { max_byte_size: body.max_byte_size };
+    let prune_watch = match body.max_byte_size {
+        Some(max_byte_size) if max_byte_size < store.byte_size => PruneWatch::new(&store, u32::MIN..=u32::MAX),
+        _ => PruneWatch::none()
+    };
     if let Err(_error) = store.update_store_defaults(&defaults) {
         return HttpResponse::InternalServerError().finish();
     }
+    prune_watch.publish(&data, &store);
+    data.events.publish(EventKind::StoreUpdated { max_byte_size: body.max_byte_size });
     config.max_byte_size = body.max_byte_size;
-    if let Err(_error) = update_config(&mut config, &data.config_location) {
+    if let Err(_error) = update_config(&data, &config) {
         return HttpResponse::InternalServerError().finish();
     }
     HttpResponse::Ok().finish()    
@@ -0,0 +1,27 @@
+use actix_web::{
+    HttpResponse,
+    web::Data
+};
+use crate::stores::Stores;
+
+use serde::{
+    Deserialize, 
+    Serialize
+};
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum Reply {
+    Ok { data: Vec<String> }
+}
+
+/// Lists the names of the named stores.
+pub fn get(stores: Data<Stores>) -> HttpResponse {
+    let named = match stores.named.read() {
+        Ok(named) => named,
+        Err(_error) => {
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    HttpResponse::Ok().json(Reply::Ok { data: named.keys().cloned().collect() })
+}
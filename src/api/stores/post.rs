@@ -0,0 +1,63 @@
+use actix_web::{
+    HttpResponse,
+    web::{
+        Data,
+        Json
+    }
+};
+use crate::{
+    api::update_config,
+    config::StoreSettings,
+    stores::{
+        start,
+        Stores,
+        valid_name
+    }
+};
+use serde::{
+    Deserialize, 
+    Serialize
+};
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Body {
+    name: String,
+    max_byte_size: Option<u32>
+}
+
+/// Creates a named store and adds it to the config file.
+///
+/// The store is only started once its settings are saved, if they cannot be the store is closed again.
+///
+/// Answers `409` if there already is a store with the name.
+pub fn post(stores: Data<Stores>, body: Json<Body>) -> HttpResponse {
+    if !valid_name(&body.name) {
+        return HttpResponse::BadRequest().finish();
+    }
+    let mut named = match stores.named.write() {
+        Ok(named) => named,
+        Err(_error) => {
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    if named.contains_key(&body.name) {
+        return HttpResponse::Conflict().finish();
+    }
+    let settings = StoreSettings {
+        max_byte_size: body.max_byte_size,
+        ..StoreSettings::default()
+    };
+    let data = match stores.open(&body.name, settings.clone()) {
+        Ok(data) => data,
+        Err(_error) => {
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    if let Err(_error) = update_config(&data, &settings) {
+        return HttpResponse::InternalServerError().finish();
+    }
+    start(&data);
+    named.insert(body.name.clone(), data);
+    HttpResponse::Ok().finish()
+}
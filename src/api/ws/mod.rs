@@ -0,0 +1,292 @@
+pub mod subscription;
+
+use actix::{
+    Actor,
+    ActorContext,
+    AsyncContext,
+    SpawnHandle,
+    StreamHandler
+};
+use actix_web::{
+    Error,
+    HttpRequest,
+    HttpResponse,
+    web::{
+        Data,
+        Payload
+    }
+};
+use actix_web_actors::ws;
+use crate::{
+    api::msg::{
+        valid_consumer_group,
+        Handoff
+    },
+    AppData,
+    events::{
+        Event,
+        EventKind
+    },
+    msg::{
+        CMD,
+        handle_packet,
+        invalid_data,
+        request,
+        response
+    },
+    stores::ScopedData
+};
+use serde_json::{
+    to_string,
+    value::Value
+};
+use std::time::{
+    Duration,
+    Instant
+};
+use subscription::Subscription;
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(60);
+const FLUSH_RETRY_INTERVAL: Duration = Duration::from_millis(10);
+
+/// A long-lived connection speaking the packet protocol defined in `crate::msg`.
+///
+/// Each text frame carries one request packet and is answered by exactly one reply packet.
+/// A client may also subscribe, after which matching messages are pushed to it as they are inserted.
+pub struct Session {
+    data: Data<AppData>,
+    heartbeat: Instant,
+    subscription: Option<Subscription>,
+    listener: Option<SpawnHandle>,
+    flush_scheduled: bool
+}
+
+impl Session {
+    pub fn new(data: Data<AppData>) -> Session {
+        Session {
+            data,
+            heartbeat: Instant::now(),
+            subscription: None,
+            listener: None,
+            flush_scheduled: false
+        }
+    }
+
+    fn start_heartbeat(&self, ctx: &mut ws::WebsocketContext<Self>) {
+        ctx.run_interval(HEARTBEAT_INTERVAL, |session, ctx| {
+            if Instant::now().duration_since(session.heartbeat) > CLIENT_TIMEOUT {
+                ctx.stop();
+                return;
+            }
+            ctx.ping(b"");
+        });
+    }
+
+    fn send(&self, ctx: &mut ws::WebsocketContext<Self>, packet: &response::Packet) {
+        match to_string(packet) {
+            Ok(packet) => ctx.text(packet),
+            Err(_error) => ctx.stop()
+        }
+    }
+
+    fn reply(&mut self, ctx: &mut ws::WebsocketContext<Self>, text: &str) {
+        let packet = match request::Packet::from_str(text) {
+            Ok(packet) => packet,
+            Err(error) => {
+                let error = response::Error::new(None, response::ErrorCode::InvalidPacket, &error);
+                self.send(ctx, &response::Packet::Error(error));
+                return;
+            }
+        };
+        let reply = match packet.cmd {
+            CMD::Subscribe => self.subscribe(ctx, packet.data),
+            CMD::Unsubscribe => self.unsubscribe(ctx),
+            CMD::Credit => self.credit(packet.data),
+            _ => handle_packet(&self.data, packet)
+        };
+        self.send(ctx, &reply);
+        self.flush(ctx);
+    }
+
+    fn subscribe(&mut self, ctx: &mut ws::WebsocketContext<Self>, packet_data: Option<Value>) -> response::Packet {
+        let cmd = CMD::Subscribe;
+        let body: request::Subscribe = match packet_data {
+            Some(packet_data) => match request::from_value(Some(packet_data)) {
+                Ok(body) => body,
+                Err(error) => {
+                    return response::Packet::Error(invalid_data(cmd, error));
+                }
+            },
+            None => request::Subscribe::default()
+        };
+        match valid_consumer_group(&self.data, body.consumer_group.as_deref(), Handoff::Push) {
+            Ok(true) => (),
+            Ok(false) => {
+                return response::Packet::Error(invalid_data(cmd, "The consumer group is not one of the store's consumer groups".to_string()));
+            },
+            Err(error) => {
+                return response::Packet::Error(response::Error::from_store_error(cmd, error));
+            }
+        }
+        if let Some(listener) = self.listener.take() {
+            ctx.cancel_future(listener);
+        }
+        let subscription = Subscription::new(body);
+        let credit = subscription.credit;
+        self.subscription = Some(subscription);
+        self.listener = Some(ctx.add_stream(self.data.events.subscribe()));
+        response::Packet::Subscription(response::Subscription { cmd, credit })
+    }
+
+    fn unsubscribe(&mut self, ctx: &mut ws::WebsocketContext<Self>) -> response::Packet {
+        if let Some(listener) = self.listener.take() {
+            ctx.cancel_future(listener);
+        }
+        self.subscription = None;
+        response::Packet::Subscription(response::Subscription { cmd: CMD::Unsubscribe, credit: 0 })
+    }
+
+    fn credit(&mut self, packet_data: Option<Value>) -> response::Packet {
+        let cmd = CMD::Credit;
+        let body: request::Credit = match request::from_value(packet_data) {
+            Ok(body) => body,
+            Err(error) => {
+                return response::Packet::Error(invalid_data(cmd, error));
+            }
+        };
+        let credit = match self.subscription.as_mut() {
+            Some(subscription) => {
+                subscription.add_credit(body.credit);
+                subscription.credit
+            },
+            None => {
+                return response::Packet::Error(invalid_data(cmd, "No active subscription".to_string()));
+            }
+        };
+        response::Packet::Subscription(response::Subscription { cmd, credit })
+    }
+
+    /// Pushes pending messages while the subscription has credit.
+    ///
+    /// If the store is busy the flush is retried shortly instead of blocking the connection.
+    fn flush(&mut self, ctx: &mut ws::WebsocketContext<Self>) {
+        let subscription = match self.subscription.as_mut() {
+            Some(subscription) => subscription,
+            None => {
+                return;
+            }
+        };
+        if !subscription.is_ready() {
+            return;
+        }
+        let msgs = {
+            let mut store = match self.data.store.try_lock() {
+                Ok(store) => store,
+                Err(_error) => {
+                    if !self.flush_scheduled {
+                        self.flush_scheduled = true;
+                        ctx.run_later(FLUSH_RETRY_INTERVAL, |session, ctx| {
+                            session.flush_scheduled = false;
+                            session.flush(ctx);
+                        });
+                    }
+                    return;
+                }
+            };
+            match subscription.take(&self.data, &mut store) {
+                Ok(msgs) => msgs,
+                Err(_error) => {
+                    ctx.stop();
+                    return;
+                }
+            }
+        };
+        for data in msgs {
+            self.send(ctx, &response::Packet::Push(response::Push { cmd: CMD::Push, data }));
+        }
+    }
+}
+
+impl Actor for Session {
+    type Context = ws::WebsocketContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.start_heartbeat(ctx);
+    }
+}
+
+impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for Session {
+    fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
+        let msg = match msg {
+            Ok(msg) => msg,
+            Err(_error) => {
+                ctx.stop();
+                return;
+            }
+        };
+        match msg {
+            ws::Message::Ping(msg) => {
+                self.heartbeat = Instant::now();
+                ctx.pong(&msg);
+            },
+            ws::Message::Pong(_) => {
+                self.heartbeat = Instant::now();
+            },
+            ws::Message::Text(text) => {
+                self.heartbeat = Instant::now();
+                self.reply(ctx, &text);
+            },
+            ws::Message::Binary(bytes) => {
+                self.heartbeat = Instant::now();
+                match std::str::from_utf8(&bytes) {
+                    Ok(text) => self.reply(ctx, text),
+                    Err(_error) => ctx.stop()
+                }
+            },
+            ws::Message::Close(reason) => {
+                ctx.close(reason);
+                ctx.stop();
+            },
+            ws::Message::Continuation(_) => {
+                ctx.stop();
+            },
+            ws::Message::Nop => ()
+        }
+    }
+}
+
+impl StreamHandler<Event> for Session {
+    fn handle(&mut self, event: Event, ctx: &mut Self::Context) {
+        let subscription = match self.subscription.as_mut() {
+            Some(subscription) => subscription,
+            None => {
+                return;
+            }
+        };
+        match event.kind {
+            EventKind::Inserted(msg)
+            | EventKind::Due(msg)
+            | EventKind::Released(msg)
+            | EventKind::Requeued(msg)
+            | EventKind::Moved { msg, .. } => {
+                subscription.queue(msg.uuid, msg.priority);
+                self.flush(ctx);
+            },
+            _ => ()
+        }
+    }
+
+    /// The store drops listeners that fall too far behind, the client is disconnected
+    /// rather than left with a subscription that no longer receives anything.
+    fn finished(&mut self, ctx: &mut Self::Context) {
+        self.listener = None;
+        if self.subscription.is_some() {
+            ctx.stop();
+        }
+    }
+}
+
+pub async fn ws(req: HttpRequest, stream: Payload, data: ScopedData) -> Result<HttpResponse, Error> {
+    ws::start(Session::new(data.into_inner()), &req, stream)
+}
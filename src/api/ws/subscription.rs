@@ -0,0 +1,155 @@
+use crate::{
+    api::msg::{
+        deliver,
+        get::MsgData,
+        Handoff,
+        Limit
+    },
+    AppData,
+    init::Store,
+    meta::{
+        now_millis,
+        Headers
+    },
+    msg::request
+};
+use msg_store::{
+    errors::Error,
+    GetOptions,
+    Uuid
+};
+use std::{
+    cmp::Reverse,
+    collections::BTreeSet
+};
+
+pub const DEFAULT_CREDIT: u32 = 1;
+/// The most messages held back for a client that is out of credit
+pub const MAX_PENDING: usize = 1000;
+
+/// The state of a client subscription.
+///
+/// Newly inserted messages that match the filter are queued as pending uuids and are only
+/// pushed while the client has credit left. Pending uuids are kept in the store's
+/// highest priority then oldest order so a backlog drains the same way `GET /api/msg` would.
+/// Past `MAX_PENDING` the oldest message of the lowest priority is dropped, the same one the store
+/// would prune first. Dropped messages stay in the store, they are just not pushed.
+pub struct Subscription {
+    priority: Option<u32>,
+    range_start: Option<u32>,
+    range_end: Option<u32>,
+    headers: Headers,
+    pub consumer_group: Option<String>,
+    pub credit: u32,
+    pending: BTreeSet<(Reverse<u32>, Uuid)>
+}
+
+impl Subscription {
+    pub fn new(request: request::Subscribe) -> Subscription {
+        Subscription {
+            priority: request.priority,
+            range_start: request.range_start,
+            range_end: request.range_end,
+            headers: request.headers.unwrap_or_default(),
+            consumer_group: request.consumer_group,
+            credit: request.credit.unwrap_or(DEFAULT_CREDIT),
+            pending: BTreeSet::new()
+        }
+    }
+
+    pub fn matches(&self, priority: u32) -> bool {
+        if let Some(wanted) = self.priority {
+            return wanted == priority;
+        }
+        let start = self.range_start.unwrap_or(u32::MIN);
+        let end = self.range_end.unwrap_or(u32::MAX);
+        start <= priority && priority <= end
+    }
+
+    pub fn queue(&mut self, uuid: Uuid, priority: u32) {
+        if !self.matches(priority) {
+            return;
+        }
+        self.pending.insert((Reverse(priority), uuid));
+        if self.pending.len() > MAX_PENDING {
+            self.drop_oldest();
+        }
+    }
+
+    /// Drops the oldest pending message of the lowest priority.
+    fn drop_oldest(&mut self) {
+        let lowest = match self.pending.iter().next_back() {
+            Some((lowest, _uuid)) => *lowest,
+            None => {
+                return;
+            }
+        };
+        let oldest = self.pending.range((lowest, Uuid { timestamp: 0, sequence: 0 })..).next().copied();
+        if let Some(oldest) = oldest {
+            self.pending.remove(&oldest);
+        }
+    }
+
+    pub fn add_credit(&mut self, credit: u32) {
+        self.credit = self.credit.saturating_add(credit);
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.credit > 0 && !self.pending.is_empty()
+    }
+
+    /// Takes as many pending messages from the store as the credit allows.
+    ///
+    /// Messages are handed out by `deliver`, so the same messages are skipped as for a get:
+    /// messages other consumers have claimed, dead letters, expired messages, messages the consumer group
+    /// has acked and messages without the headers of the filter. Skipped messages do not use up credit.
+    /// Delayed messages are dropped from the pending ones and queued again once they are due.
+    pub fn take(&mut self, data: &AppData, store: &mut Store) -> Result<Vec<MsgData>, Error> {
+        {
+            let meta = data.meta.try_lock().map_err(|_error| Error::SyncError)?;
+            let now = now_millis();
+            self.pending.retain(|(_priority, uuid)| store.id_to_group_map.contains_key(uuid) && !meta.is_delayed(uuid, now));
+        }
+        let mut msgs = vec![];
+        while self.credit > 0 {
+            let next = match self.pending.iter().next() {
+                Some(next) => *next,
+                None => break
+            };
+            self.pending.remove(&next);
+            let (_priority, uuid) = next;
+            let options = GetOptions::default().uuid(uuid);
+            let limit = Limit { count: 1, max_bytes: None };
+            let deliveries = deliver(data, store, &options, &self.headers, self.consumer_group.as_deref(), Handoff::Push, limit)?;
+            for delivery in deliveries {
+                msgs.push(MsgData::from_delivery(delivery));
+                self.credit -= 1;
+            }
+        }
+        Ok(msgs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pending_drops_the_oldest_lowest_priority_first() {
+        let mut subscription = Subscription::new(request::Subscribe::default());
+        let uuid = |timestamp: u128| Uuid { timestamp, sequence: 1 };
+        for timestamp in 0..MAX_PENDING as u128 {
+            subscription.queue(uuid(timestamp + 10), 2);
+        }
+        subscription.queue(uuid(1), 1);
+        subscription.queue(uuid(2), 1);
+        assert_eq!(subscription.pending.len(), MAX_PENDING);
+        assert!(!subscription.pending.contains(&(Reverse(1), uuid(1))));
+        assert!(!subscription.pending.contains(&(Reverse(1), uuid(2))));
+        subscription.queue(uuid(5000), 3);
+        assert_eq!(subscription.pending.len(), MAX_PENDING);
+        assert!(!subscription.pending.contains(&(Reverse(2), uuid(10))));
+        assert!(subscription.pending.contains(&(Reverse(2), uuid(11))));
+        assert!(subscription.pending.contains(&(Reverse(3), uuid(5000))));
+    }
+}
@@ -0,0 +1,89 @@
+use crate::{
+    config::{
+        CompressionAlgorithm,
+        CompressionConfig
+    },
+    meta::{
+        byte_size,
+        Meta
+    }
+};
+use flate2::{
+    Compression,
+    read::GzDecoder,
+    write::GzEncoder
+};
+use msg_store::errors::{
+    DbError,
+    Error
+};
+use std::io::{
+    Read,
+    Write
+};
+
+const DEFAULT_MIN_BYTE_SIZE: u32 = 256;
+
+/// The bytes of the message, raw messages are kept base64 encoded.
+fn msg_bytes(meta: &Meta, msg: &str) -> Option<Vec<u8>> {
+    if meta.binary {
+        base64::decode(msg).ok()
+    } else {
+        Some(msg.as_bytes().to_vec())
+    }
+}
+
+fn gzip(bytes: &[u8]) -> Option<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(bytes).ok()?;
+    encoder.finish().ok()
+}
+
+fn gunzip(bytes: &[u8]) -> Option<Vec<u8>> {
+    let mut decoder = GzDecoder::new(bytes);
+    let mut decompressed = vec![];
+    decoder.read_to_end(&mut decompressed).ok()?;
+    Some(decompressed)
+}
+
+/// Compresses the message to be written to the database, marking it in the meta data.
+///
+/// The message is returned as it is if it is below the size threshold or would not get any smaller.
+pub fn compress(config: &CompressionConfig, meta: &mut Meta, msg: String) -> String {
+    if byte_size(meta, &msg) < config.min_byte_size.unwrap_or(DEFAULT_MIN_BYTE_SIZE) {
+        return msg;
+    }
+    let compressed = match msg_bytes(meta, &msg).and_then(|bytes| match config.algorithm {
+        CompressionAlgorithm::Gzip => gzip(&bytes)
+    }) {
+        Some(compressed) => base64::encode(compressed),
+        None => {
+            return msg;
+        }
+    };
+    if compressed.len() >= msg.len() {
+        return msg;
+    }
+    meta.compressed = Some(config.algorithm);
+    compressed
+}
+
+/// Gives back a message as it was before it was compressed.
+pub fn decompress(meta: &Meta, msg: String) -> Result<String, Error> {
+    let algorithm = match meta.compressed {
+        Some(algorithm) => algorithm,
+        None => {
+            return Ok(msg);
+        }
+    };
+    let corrupt = || Error::DbError(DbError("Could not decompress message".to_string()));
+    let compressed = base64::decode(&msg).map_err(|_error| corrupt())?;
+    let bytes = match algorithm {
+        CompressionAlgorithm::Gzip => gunzip(&compressed).ok_or_else(corrupt)?
+    };
+    if meta.binary {
+        Ok(base64::encode(bytes))
+    } else {
+        String::from_utf8(bytes).map_err(|_error| corrupt())
+    }
+}
@@ -4,22 +4,154 @@ use serde::{
 };
 use serde_json::{
     to_string_pretty as to_json_string,
-    from_str as from_json_str
+    to_value,
+    from_str as from_json_str,
+    from_value,
+    Value
 };
 use std::{
+    collections::BTreeMap,
     fs::{
         self,
         read_to_string
     },
     path::{
         PathBuf
-    }
+    },
+    sync::Mutex
 };
 
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(deny_unknown_fields)]
 pub struct GroupConfig {
     pub priority: u32,
-    pub max_byte_size: Option<u32>
+    pub max_byte_size: Option<u32>,
+    /// Default time to live for messages in the group, such as "30s" or "12h"
+    pub ttl: Option<String>,
+    /// Overrides the store's compression setting for the group
+    pub compression: Option<CompressionConfig>
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum CompressionAlgorithm {
+    Gzip
+}
+
+/// Settings for compressing messages before they are written to the database
+///
+/// Messages smaller than `min_byte_size` and messages that do not get any smaller are stored as they are.
+/// The byte limits always apply to the size of the message before compression.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(deny_unknown_fields)]
+pub struct CompressionConfig {
+    pub algorithm: CompressionAlgorithm,
+    pub min_byte_size: Option<u32>
+}
+
+/// Settings for setting aside messages that keep failing delivery
+///
+/// A message whose lease runs out or is nacked after `max_attempts` claims becomes a dead letter.
+/// Dead letters are moved to `priority` if it is set, otherwise they stay in their own group.
+/// Either way they are no longer handed to consumers until they are requeued.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+pub struct DeadLetterConfig {
+    pub max_attempts: u32,
+    pub priority: Option<u32>
+}
+
+/// Settings for handing out messages that have waited long enough as if they had a higher priority
+///
+/// A message gains one priority level for every `interval` it has been in the store, such as "30s",
+/// up to `max_boost` levels. Only the order consumers get messages in changes,
+/// pruning still goes by the priority the message was stored with.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct AgingConfig {
+    pub interval: String,
+    pub max_boost: u32
+}
+
+/// What the weights of a fair dequeue share out
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ShareUnit {
+    Msgs,
+    Bytes
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+pub struct GroupWeight {
+    pub priority: u32,
+    pub weight: u32
+}
+
+/// Settings for sharing out dequeues between priority groups by weight instead of strictly by priority
+///
+/// The weights share out messages unless `unit` is "bytes".
+/// Groups without a weight are only served once the weighted groups are empty.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct FairDequeueConfig {
+    pub weights: Vec<GroupWeight>,
+    pub unit: Option<ShareUnit>
+}
+
+/// Settings for the raw tcp listener that speaks newline delimited packets
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TcpConfig {
+    pub host: Option<String>,
+    pub port: u32
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum SocketProtocol {
+    Http,
+    Packet
+}
+
+/// Settings for serving on a unix domain socket
+///
+/// The mode is the octal file permission set on the socket, e.g. "660".
+/// The protocol defaults to http.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct UnixSocketConfig {
+    pub path: PathBuf,
+    pub mode: Option<String>,
+    pub protocol: Option<SocketProtocol>
+}
+
+/// The settings each store keeps for itself
+///
+/// The default store reads them from the top level of the config file,
+/// named stores from their entry in `stores`.
+#[derive(Debug, Default, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct StoreSettings {
+    pub max_byte_size: Option<u32>,
+    pub groups: Option<Vec<GroupConfig>>,
+    pub dead_letter: Option<DeadLetterConfig>,
+    pub idempotency_window: Option<String>,
+    pub compression: Option<CompressionConfig>,
+    /// Fans messages out to these consumer groups, each of which has to ack a message before it is deleted
+    pub consumer_groups: Option<Vec<String>>,
+    pub aging: Option<AgingConfig>,
+    pub fair_dequeue: Option<FairDequeueConfig>,
+    /// The most messages one get hands out, however many it asks for
+    pub max_batch_size: Option<u32>,
+    /// The most bytes one get hands out, past the first message
+    pub max_batch_bytes: Option<u32>
+}
+
+/// The config file all stores write their settings back to
+pub struct ConfigFile {
+    pub location: Option<PathBuf>,
+    pub config: Mutex<StoreConfig>
 }
 
 cfg_if::cfg_if! {
@@ -30,18 +162,28 @@ cfg_if::cfg_if! {
         pub struct StoreConfig {
             pub host: Option<String>,
             pub port: Option<u32>,
-            pub max_byte_size: Option<u32>,
-            pub groups: Option<Vec<GroupConfig>>,
-            pub no_update: Option<bool>
+            #[serde(flatten)]
+            pub settings: StoreSettings,
+            pub no_update: Option<bool>,
+            pub stores: Option<BTreeMap<String, StoreSettings>>,
+            pub no_http: Option<bool>,
+            pub tcp: Option<TcpConfig>,
+            pub unix_socket: Option<UnixSocketConfig>,
+            /// The most bytes the body of a `POST /api/msgs` request may have
+            pub max_msgs_body_size: Option<usize>
         }
         impl StoreConfig {
             pub fn new() -> StoreConfig {
                 StoreConfig {
                     host: Some("127.0.0.1".to_string()),
                     port: Some(8080),
-                    max_byte_size: None,
-                    groups: None,
-                    no_update: Some(false)
+                    settings: StoreSettings::default(),
+                    no_update: Some(false),
+                    stores: None,
+                    no_http: None,
+                    tcp: None,
+                    unix_socket: None,
+                    max_msgs_body_size: None
                 }
             }
         }
@@ -49,30 +191,52 @@ cfg_if::cfg_if! {
     } else {
 
         #[derive(Debug, Deserialize, Serialize, Clone)]
+        #[serde(deny_unknown_fields)]
         pub struct LeveldbConfig {
             pub location: Option<PathBuf>
         }
 
+        /// Settings for encrypting messages before they are written to the database
+        ///
+        /// The key file holds a 32 byte key, base64 encoded.
+        #[derive(Debug, Deserialize, Serialize, Clone)]
+        #[serde(deny_unknown_fields)]
+        pub struct EncryptionConfig {
+            pub key_file: PathBuf
+        }
+
         #[derive(Debug, Deserialize, Serialize)]
         pub struct StoreConfig {
             pub host: Option<String>,
             pub port: Option<u32>,
-            pub max_byte_size: Option<u32>,
-            pub groups: Option<Vec<GroupConfig>>,
+            #[serde(flatten)]
+            pub settings: StoreSettings,
             pub leveldb: Option<LeveldbConfig>,
-            pub no_update: Option<bool>
+            pub no_update: Option<bool>,
+            pub encryption: Option<EncryptionConfig>,
+            pub stores: Option<BTreeMap<String, StoreSettings>>,
+            pub no_http: Option<bool>,
+            pub tcp: Option<TcpConfig>,
+            pub unix_socket: Option<UnixSocketConfig>,
+            /// The most bytes the body of a `POST /api/msgs` request may have
+            pub max_msgs_body_size: Option<usize>
         }
         impl StoreConfig {
             pub fn new() -> StoreConfig {
                 StoreConfig {
                     host: Some("127.0.0.1".to_string()),
                     port: Some(8080),
-                    max_byte_size: None,
-                    groups: None,
+                    settings: StoreSettings::default(),
                     leveldb: Some(LeveldbConfig {
                         location: None
                     }),
-                    no_update: None
+                    no_update: None,
+                    encryption: None,
+                    stores: None,
+                    no_http: None,
+                    tcp: None,
+                    unix_socket: None,
+                    max_msgs_body_size: None
                 }
             }
         }
@@ -87,7 +251,22 @@ impl StoreConfig {
     
     pub fn open(config_path: PathBuf) -> StoreConfig {
         let contents: String = read_to_string(config_path).expect("Could not read config");
-        from_json_str(&contents).expect("Invalid JSON config.")
+        StoreConfig::parse(&contents).unwrap_or_else(|error| panic!("Invalid JSON config: {}", error))
+    }
+
+    /// Reads a config, rejecting any field it does not know.
+    ///
+    /// The store settings are flattened into the top level, which serde cannot combine with
+    /// `deny_unknown_fields`, so the top level fields are checked against those of a new config.
+    pub fn parse(contents: &str) -> Result<StoreConfig, String> {
+        let value: Value = from_json_str(contents).map_err(|error| error.to_string())?;
+        let known = to_value(StoreConfig::new()).map_err(|error| error.to_string())?;
+        if let (Some(fields), Some(known)) = (value.as_object(), known.as_object()) {
+            if let Some(field) = fields.keys().find(|field| !known.contains_key(*field)) {
+                return Err(format!("unknown field `{}`", field));
+            }
+        }
+        from_value(value).map_err(|error| error.to_string())
     }
     pub fn update_config_file(&self, config_path: &PathBuf) -> Result<(), String> {
         let contents = match to_json_string(&self) {
@@ -106,3 +285,25 @@ impl StoreConfig {
 // validate config file
 // if conf is invalid, panic
 // read config
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        assert!(StoreConfig::parse(r#"{ "port": 8080, "max_byte_size": 100, "stores": { "a": { "max_byte_size": 10 } } }"#).is_ok());
+        assert!(StoreConfig::parse(r#"{ "port": 8080, "max_bytes_size": 100 }"#).is_err());
+        assert!(StoreConfig::parse(r#"{ "stores": { "a": { "max_bytes_size": 10 } } }"#).is_err());
+        assert!(StoreConfig::parse(r#"{ "groups": [{ "priority": 1, "ttl": "1s", "tll": "1s" }] }"#).is_err());
+        assert!(StoreConfig::parse(r#"{ "compression": { "algorithm": "gzip", "min_size": 10 } }"#).is_err());
+    }
+
+    #[cfg(feature = "level")]
+    #[test]
+    fn encryption_is_read_from_the_top_level() {
+        let config = StoreConfig::parse(r#"{ "leveldb": { "location": "/tmp/store" }, "encryption": { "key_file": "/tmp/key" } }"#).unwrap();
+        assert_eq!(config.encryption.unwrap().key_file, PathBuf::from("/tmp/key"));
+        assert!(StoreConfig::parse(r#"{ "leveldb": { "location": "/tmp/store", "encryption": { "key_file": "/tmp/key" } } }"#).is_err());
+    }
+}
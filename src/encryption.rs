@@ -0,0 +1,303 @@
+use crate::{
+    api::msg::msg_event,
+    config::EncryptionConfig,
+    init::{
+        InitResult,
+        open_store,
+        Store
+    },
+    meta::{
+        decode,
+        encode,
+        Meta
+    }
+};
+use chacha20poly1305::{
+    aead::{
+        Aead,
+        NewAead
+    },
+    Key,
+    XChaCha20Poly1305,
+    XNonce
+};
+use msg_store::{
+    errors::{
+        DbError,
+        Error
+    },
+    GetOptions,
+    Keeper,
+    Package,
+    Uuid
+};
+use std::{
+    fs,
+    io,
+    path::{
+        Path,
+        PathBuf
+    }
+};
+
+const KEY_SIZE: usize = 32;
+const NONCE_SIZE: usize = 24;
+
+fn crypto_error(message: &str) -> Error {
+    Error::DbError(DbError(message.to_string()))
+}
+
+/// Encrypts messages before they are written to the database with XChaCha20-Poly1305.
+///
+/// Each message gets its own random nonce, which is kept in front of the cipher text.
+#[derive(Clone)]
+pub struct Cipher {
+    cipher: XChaCha20Poly1305
+}
+
+impl Cipher {
+    /// Loads the key from a file holding 32 bytes, base64 encoded.
+    pub fn open(key_file: &Path) -> Result<Cipher, String> {
+        let contents = fs::read_to_string(key_file).map_err(|error| error.to_string())?;
+        let key = base64::decode(contents.trim()).map_err(|error| error.to_string())?;
+        if key.len() != KEY_SIZE {
+            return Err(format!("The key must be {} bytes", KEY_SIZE));
+        }
+        Ok(Cipher { cipher: XChaCha20Poly1305::new(Key::from_slice(&key)) })
+    }
+
+    pub fn encrypt(&self, msg: &str) -> Result<String, Error> {
+        let mut nonce = [0u8; NONCE_SIZE];
+        getrandom::getrandom(&mut nonce).map_err(|_error| crypto_error("Could not generate a nonce"))?;
+        let cipher_text = self.cipher.encrypt(XNonce::from_slice(&nonce), msg.as_bytes())
+            .map_err(|_error| crypto_error("Could not encrypt message"))?;
+        let mut sealed = nonce.to_vec();
+        sealed.extend(cipher_text);
+        Ok(base64::encode(sealed))
+    }
+
+    pub fn decrypt(&self, msg: &str) -> Result<String, Error> {
+        let sealed = base64::decode(msg).map_err(|_error| crypto_error("Could not decrypt message"))?;
+        if sealed.len() < NONCE_SIZE {
+            return Err(crypto_error("Could not decrypt message"));
+        }
+        let (nonce, cipher_text) = sealed.split_at(NONCE_SIZE);
+        let msg = self.cipher.decrypt(XNonce::from_slice(nonce), cipher_text)
+            .map_err(|_error| crypto_error("Could not decrypt message"))?;
+        String::from_utf8(msg).map_err(|_error| crypto_error("Could not decrypt message"))
+    }
+}
+
+/// Decrypts a message read from the database if it was stored encrypted.
+pub fn decrypt(cipher: Option<&Cipher>, meta: &Meta, msg: String) -> Result<String, Error> {
+    if !meta.encrypted {
+        return Ok(msg);
+    }
+    match cipher {
+        Some(cipher) => cipher.decrypt(&msg),
+        None => Err(crypto_error("The message is encrypted but no key is configured"))
+    }
+}
+
+/// Loads the key set in the config, if there is one.
+pub fn load(encryption: &Option<EncryptionConfig>) -> Result<Option<Cipher>, String> {
+    match encryption {
+        Some(encryption) => Ok(Some(Cipher::open(&encryption.key_file)?)),
+        None => Ok(None)
+    }
+}
+
+/// Encrypts every stored message with the new key.
+///
+/// Messages encrypted with the old key are decrypted first and messages that were stored
+/// before encryption was turned on are encrypted as well. Messages the new key can already
+/// read are left alone, so a rotation that was cut short can be run again.
+///
+/// Returns the number of messages that were re-encrypted.
+fn rotate(store: &mut Store, old_cipher: Option<&Cipher>, new_cipher: &Cipher) -> Result<u32, Error> {
+    let uuids: Vec<Uuid> = store.id_to_group_map.keys().copied().collect();
+    let mut rotated = 0;
+    for uuid in uuids {
+        let msg = match msg_event(store, &uuid) {
+            Some(msg) => msg,
+            None => continue
+        };
+        let body = store.get(GetOptions::default().uuid(uuid))?.ok_or(Error::SyncError)?.msg;
+        let (mut meta, payload) = decode(body);
+        let payload = if meta.encrypted {
+            if new_cipher.decrypt(&payload).is_ok() {
+                continue;
+            }
+            old_cipher.ok_or_else(|| crypto_error("The stored messages are encrypted but no key is configured"))?.decrypt(&payload)?
+        } else {
+            payload
+        };
+        meta.encrypted = true;
+        let package = Package {
+            uuid,
+            priority: msg.priority,
+            msg: encode(&meta, new_cipher.encrypt(&payload)?),
+            byte_size: msg.byte_size
+        };
+        store.db.add(&package).map_err(Error::DbError)?;
+        rotated += 1;
+    }
+    Ok(rotated)
+}
+
+/// Re-encrypts the default store and every named store with the key in the file and points the config at it.
+fn rotate_key(init_result: &mut InitResult, old_cipher: Option<&Cipher>, key_file: PathBuf) -> Result<u32, String> {
+    let new_cipher = Cipher::open(&key_file)?;
+    let mut rotated = rotate(&mut init_result.store, old_cipher, &new_cipher).map_err(|error| format!("{:?}", error))?;
+    let names: Vec<String> = init_result.store_config.stores.iter().flat_map(|stores| stores.keys().cloned()).collect();
+    for name in names {
+        let (mut store, _idempotency_location) = open_store(&init_result.stores_location, &name)?;
+        rotated += rotate(&mut store, old_cipher, &new_cipher).map_err(|error| format!("{:?}", error))?;
+    }
+    init_result.store_config.encryption = Some(EncryptionConfig { key_file });
+    if let Some(config_location) = &init_result.config_location {
+        init_result.store_config.update_config_file(config_location)?;
+    }
+    Ok(rotated)
+}
+
+/// Runs the `rotate-key` command, logging how many messages it re-encrypted.
+///
+/// Main returns the result, so a failed rotation exits with an error.
+pub fn run_rotate_key(init_result: &mut InitResult, old_cipher: Option<&Cipher>, key_file: PathBuf) -> io::Result<()> {
+    let rotated = rotate_key(init_result, old_cipher, key_file)
+        .map_err(|error| io::Error::new(io::ErrorKind::Other, format!("Could not rotate the encryption key: {}", error)))?;
+    log::info!("Re-encrypted {} messages", rotated);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        api::msg::{
+            insert,
+            read
+        },
+        AppData,
+        config::{
+            ConfigFile,
+            StoreConfig,
+            StoreSettings
+        }
+    };
+    use std::{
+        process,
+        sync::{
+            Arc,
+            Mutex
+        }
+    };
+
+    /// A database directory and key files of its own for each test, removed again when the test is done.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> TempDir {
+            let path = std::env::temp_dir().join(format!("msg-store-encryption-{}-{}", name, process::id()));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            TempDir(path)
+        }
+
+        fn key_file(&self, name: &str, byte: u8) -> PathBuf {
+            let key_file = self.0.join(name);
+            fs::write(&key_file, base64::encode([byte; KEY_SIZE])).unwrap();
+            key_file
+        }
+
+        fn open_store(&self) -> Store {
+            open_store(&Some(self.0.clone()), "store").unwrap().0
+        }
+
+        fn open(&self, cipher: Option<Cipher>) -> AppData {
+            let config_file = Arc::new(ConfigFile { location: None, config: Mutex::new(StoreConfig::new()) });
+            AppData::open(None, self.open_store(), None, StoreSettings::default(), config_file, cipher).unwrap()
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn read_msg(data: &AppData, uuid: &Uuid) -> Result<String, Error> {
+        let mut store = data.store.lock().unwrap();
+        Ok(read(data, &mut store, uuid)?.unwrap().1)
+    }
+
+    #[test]
+    fn encrypted_msgs_are_read_back_after_a_restart() {
+        let dir = TempDir::new("reopen");
+        let cipher = Cipher::open(&dir.key_file("key", 1)).unwrap();
+        let data = dir.open(Some(cipher.clone()));
+        let uuid = {
+            let mut store = data.store.lock().unwrap();
+            insert(&data, &mut store, 1, "secret".to_string(), Meta::default()).unwrap()
+        };
+        drop(data);
+
+        let mut store = dir.open_store();
+        let (meta, stored) = decode(store.get(GetOptions::default().uuid(uuid)).unwrap().unwrap().msg);
+        assert!(meta.encrypted);
+        assert_ne!(stored, "secret");
+        drop(store);
+
+        let data = dir.open(Some(cipher));
+        assert_eq!(read_msg(&data, &uuid).unwrap(), "secret");
+        drop(data);
+        let data = dir.open(None);
+        assert!(read_msg(&data, &uuid).is_err());
+    }
+
+    #[test]
+    fn rotated_msgs_are_only_read_with_the_new_key() {
+        let dir = TempDir::new("rotate");
+        let old_cipher = Cipher::open(&dir.key_file("old-key", 1)).unwrap();
+        let new_key_file = dir.key_file("new-key", 2);
+        let new_cipher = Cipher::open(&new_key_file).unwrap();
+        let (plain, encrypted) = {
+            let data = dir.open(None);
+            let mut store = data.store.lock().unwrap();
+            let plain = insert(&data, &mut store, 1, "stored before encryption".to_string(), Meta::default()).unwrap();
+            drop(store);
+            drop(data);
+            let data = dir.open(Some(old_cipher.clone()));
+            let mut store = data.store.lock().unwrap();
+            let encrypted = insert(&data, &mut store, 2, "stored with the old key".to_string(), Meta::default()).unwrap();
+            (plain, encrypted)
+        };
+
+        let mut init_result = InitResult {
+            host: None,
+            tcp_host: None,
+            unix_socket: None,
+            store: dir.open_store(),
+            idempotency_location: None,
+            stores_location: Some(dir.0.join("stores")),
+            store_config: StoreConfig::new(),
+            config_location: None,
+            update_config: false,
+            rotate_key: None
+        };
+        assert_eq!(rotate_key(&mut init_result, Some(&old_cipher), new_key_file.clone()), Ok(2));
+        assert_eq!(init_result.store_config.encryption.as_ref().map(|encryption| encryption.key_file.clone()), Some(new_key_file.clone()));
+        // running it again after it was cut short leaves what the new key can read alone
+        assert_eq!(rotate_key(&mut init_result, Some(&old_cipher), new_key_file), Ok(0));
+        drop(init_result);
+
+        let data = dir.open(Some(new_cipher));
+        assert_eq!(read_msg(&data, &plain).unwrap(), "stored before encryption");
+        assert_eq!(read_msg(&data, &encrypted).unwrap(), "stored with the old key");
+        drop(data);
+        let data = dir.open(Some(old_cipher));
+        assert!(read_msg(&data, &plain).is_err());
+        assert!(read_msg(&data, &encrypted).is_err());
+    }
+}
@@ -0,0 +1,101 @@
+use futures::channel::mpsc::{
+    channel,
+    Receiver,
+    Sender
+};
+use msg_store::Uuid;
+use std::{
+    sync::Mutex,
+    time::{
+        SystemTime,
+        UNIX_EPOCH
+    }
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct MsgEvent {
+    pub uuid: Uuid,
+    pub priority: u32,
+    pub byte_size: u32
+}
+
+#[derive(Debug, Clone)]
+pub enum EventKind {
+    Inserted(MsgEvent),
+    Deleted(MsgEvent),
+    Pruned(MsgEvent),
+    Exported(MsgEvent),
+    Released(MsgEvent),
+    Expired(MsgEvent),
+    Due(MsgEvent),
+    DeadLettered(MsgEvent),
+    Requeued(MsgEvent),
+    Moved { msg: MsgEvent, previous_priority: u32 },
+    GroupDefaultsUpdated { priority: u32, max_byte_size: Option<u32> },
+    GroupDefaultsDeleted { priority: u32 },
+    StoreUpdated { max_byte_size: Option<u32> }
+}
+
+#[derive(Debug, Clone)]
+pub struct Event {
+    /// Milliseconds since the unix epoch at which the event was published
+    pub timestamp: u64,
+    pub kind: EventKind
+}
+
+/// The most events a listener can fall behind by before it is dropped
+pub const LISTENER_CAPACITY: usize = 1024;
+
+/// Fans store events out to every live listener.
+///
+/// Listeners that have gone away are dropped the next time an event is published,
+/// as are listeners that fall `LISTENER_CAPACITY` events behind. Their stream ends,
+/// so a slow listener cannot hold on to an ever growing backlog.
+pub struct Events {
+    listeners: Mutex<Vec<Sender<Event>>>
+}
+
+impl Events {
+    pub fn new() -> Events {
+        Events {
+            listeners: Mutex::new(vec![])
+        }
+    }
+
+    pub fn subscribe(&self) -> Receiver<Event> {
+        let (sender, receiver) = channel(LISTENER_CAPACITY);
+        if let Ok(mut listeners) = self.listeners.lock() {
+            listeners.push(sender);
+        }
+        receiver
+    }
+
+    pub fn publish(&self, kind: EventKind) {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|since| since.as_millis() as u64).unwrap_or(0);
+        let event = Event { timestamp, kind };
+        if let Ok(mut listeners) = self.listeners.lock() {
+            listeners.retain_mut(|listener| listener.try_send(event.clone()).is_ok());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn listener_that_falls_behind_is_dropped() {
+        let events = Events::new();
+        let mut receiver = events.subscribe();
+        for _event in 0..LISTENER_CAPACITY + 2 {
+            events.publish(EventKind::StoreUpdated { max_byte_size: None });
+        }
+        let mut received = 0;
+        while let Ok(Some(_event)) = receiver.try_next() {
+            received += 1;
+        }
+        assert!(received <= LISTENER_CAPACITY + 1);
+        assert!(matches!(receiver.try_next(), Ok(None)));
+        assert!(events.listeners.lock().unwrap().is_empty());
+    }
+}
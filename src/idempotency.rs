@@ -0,0 +1,242 @@
+use crate::{
+    api::{
+        parse_duration,
+        parse_uuid
+    },
+    config::StoreSettings
+};
+use msg_store::Uuid;
+use serde::{
+    Deserialize,
+    Serialize
+};
+use serde_json::{
+    from_str,
+    to_string
+};
+use std::{
+    collections::BTreeMap,
+    fs::{
+        self,
+        OpenOptions
+    },
+    io::{
+        self,
+        Write
+    },
+    path::PathBuf,
+    time::Duration
+};
+
+const DEFAULT_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+/// A line of the key file.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Entry {
+    key: String,
+    uuid: String,
+    inserted_at: u64
+}
+
+/// Remembers the idempotency keys producers sent with their messages so a retried insert
+/// gets the original uuid back instead of adding the message again.
+///
+/// Keys are kept for the configured window, counted from the first insert, even if the
+/// message has left the store since. With a file the keys are appended to it as they
+/// come in, so they survive a restart.
+pub struct Idempotency {
+    keys: BTreeMap<String, (Uuid, u64)>,
+    window: u64,
+    file: Option<PathBuf>,
+    lines: usize,
+    /// Set when a key could not be appended to the file, so it is rewritten on the next prune
+    unsaved: bool
+}
+
+impl Idempotency {
+    /// Loads the keys still inside the window from the file, if there is one, and rewrites it without the rest.
+    pub fn open(file: Option<PathBuf>, settings: &StoreSettings, now: u64) -> io::Result<Idempotency> {
+        let window = match &settings.idempotency_window {
+            Some(window) => parse_duration(window)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Idempotency window must be a duration such as 10m"))?,
+            None => DEFAULT_WINDOW
+        };
+        let mut idempotency = Idempotency {
+            keys: BTreeMap::new(),
+            window: window.as_millis() as u64,
+            file,
+            lines: 0,
+            unsaved: false
+        };
+        if let Some(file) = &idempotency.file {
+            if file.exists() {
+                for line in fs::read_to_string(file)?.lines() {
+                    // a line cut short by a crash is skipped
+                    let entry: Entry = match from_str(line) {
+                        Ok(entry) => entry,
+                        Err(_error) => continue
+                    };
+                    if let Some(uuid) = parse_uuid(&entry.uuid) {
+                        idempotency.keys.insert(entry.key, (uuid, entry.inserted_at));
+                    }
+                }
+            }
+        }
+        idempotency.prune(now)?;
+        idempotency.compact()?;
+        Ok(idempotency)
+    }
+
+    /// The uuid of the message first inserted with the key, if that was within the window.
+    pub fn get(&self, key: &str, now: u64) -> Option<Uuid> {
+        self.keys.get(key)
+            .filter(|(_uuid, inserted_at)| now < inserted_at.saturating_add(self.window))
+            .map(|(uuid, _inserted_at)| *uuid)
+    }
+
+    /// Remembers the key, then appends it to the file.
+    ///
+    /// The key is kept even if the file cannot be written, so a retry of the insert still
+    /// gets the original uuid back. The file is then rewritten with it on the next prune.
+    pub fn insert(&mut self, key: String, uuid: Uuid, now: u64) -> io::Result<()> {
+        self.keys.insert(key.clone(), (uuid, now));
+        let entry = Entry { key, uuid: uuid.to_string(), inserted_at: now };
+        if let Err(error) = self.append(&entry) {
+            self.unsaved = true;
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    fn append(&mut self, entry: &Entry) -> io::Result<()> {
+        if let Some(file) = &self.file {
+            let line = to_string(entry).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+            let mut file = OpenOptions::new().create(true).append(true).open(file)?;
+            writeln!(file, "{}", line)?;
+            self.lines += 1;
+        }
+        Ok(())
+    }
+
+    /// Drops the keys that have fallen out of the window.
+    ///
+    /// The file is rewritten once most of its lines are for keys that are gone.
+    pub fn prune(&mut self, now: u64) -> io::Result<()> {
+        let window = self.window;
+        self.keys.retain(|_key, (_uuid, inserted_at)| now < inserted_at.saturating_add(window));
+        if self.unsaved || self.lines > self.keys.len() * 2 {
+            self.compact()?;
+        }
+        Ok(())
+    }
+
+    fn compact(&mut self) -> io::Result<()> {
+        let file = match &self.file {
+            Some(file) => file,
+            None => {
+                return Ok(());
+            }
+        };
+        let mut contents = String::new();
+        for (key, (uuid, inserted_at)) in self.keys.iter() {
+            let entry = Entry { key: key.clone(), uuid: uuid.to_string(), inserted_at: *inserted_at };
+            contents.push_str(&to_string(&entry).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?);
+            contents.push('\n');
+        }
+        let temp_file = file.with_extension("tmp");
+        fs::write(&temp_file, contents)?;
+        fs::rename(&temp_file, file)?;
+        self.lines = self.keys.len();
+        self.unsaved = false;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    /// A key file of its own for each test, removed again when the test is done.
+    struct KeyFile(PathBuf);
+
+    impl KeyFile {
+        fn new(name: &str) -> KeyFile {
+            let path = std::env::temp_dir().join(format!("msg-store-idempotency-{}-{}", name, process::id()));
+            let _ = fs::remove_file(&path);
+            KeyFile(path)
+        }
+
+        fn open(&self, now: u64) -> Idempotency {
+            let settings = StoreSettings { idempotency_window: Some("1s".to_string()), ..StoreSettings::default() };
+            Idempotency::open(Some(self.0.clone()), &settings, now).unwrap()
+        }
+
+        fn lines(&self) -> usize {
+            fs::read_to_string(&self.0).unwrap().lines().count()
+        }
+    }
+
+    impl Drop for KeyFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn uuid(timestamp: u128) -> Uuid {
+        Uuid { timestamp, sequence: 1 }
+    }
+
+    #[test]
+    fn keys_are_still_known_after_a_restart() {
+        let key_file = KeyFile::new("reopen");
+        let mut idempotency = key_file.open(0);
+        idempotency.insert("a".to_string(), uuid(1), 100).unwrap();
+        drop(idempotency);
+        let idempotency = key_file.open(200);
+        assert_eq!(idempotency.get("a", 200), Some(uuid(1)));
+        assert_eq!(idempotency.get("b", 200), None);
+        assert_eq!(idempotency.get("a", 1100), None);
+    }
+
+    #[test]
+    fn expired_keys_are_compacted_away() {
+        let key_file = KeyFile::new("compact");
+        let mut idempotency = key_file.open(0);
+        for (index, key) in ["a", "b", "c"].iter().enumerate() {
+            idempotency.insert(key.to_string(), uuid(index as u128), 0).unwrap();
+        }
+        idempotency.insert("d".to_string(), uuid(4), 900).unwrap();
+        assert_eq!(key_file.lines(), 4);
+        idempotency.prune(1100).unwrap();
+        assert_eq!(key_file.lines(), 1);
+        assert_eq!(idempotency.get("a", 1100), None);
+        assert_eq!(idempotency.get("d", 1100), Some(uuid(4)));
+        drop(idempotency);
+        // opening prunes and rewrites the file as well
+        let idempotency = key_file.open(2000);
+        assert_eq!(idempotency.get("d", 1500), None);
+        assert_eq!(key_file.lines(), 0);
+    }
+
+    #[test]
+    fn truncated_last_line_is_skipped() {
+        let key_file = KeyFile::new("truncated");
+        let mut idempotency = key_file.open(0);
+        idempotency.insert("a".to_string(), uuid(1), 0).unwrap();
+        drop(idempotency);
+        let mut file = OpenOptions::new().append(true).open(&key_file.0).unwrap();
+        write!(file, "{{\"key\":\"b\",\"uu").unwrap();
+        drop(file);
+        let mut idempotency = key_file.open(100);
+        assert_eq!(idempotency.get("a", 100), Some(uuid(1)));
+        assert_eq!(idempotency.get("b", 100), None);
+        assert_eq!(key_file.lines(), 1);
+        idempotency.insert("c".to_string(), uuid(3), 200).unwrap();
+        drop(idempotency);
+        let idempotency = key_file.open(300);
+        assert_eq!(idempotency.get("a", 300), Some(uuid(1)));
+        assert_eq!(idempotency.get("c", 300), Some(uuid(3)));
+    }
+}
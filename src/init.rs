@@ -1,6 +1,8 @@
 
 
-use clap::{Arg, ArgMatches,App};
+use clap::{Arg, ArgMatches,App, Error, ErrorKind};
+#[cfg(feature = "level")]
+use clap::SubCommand;
 use dirs::home_dir;
 use serde_json::{
     to_string_pretty
@@ -13,12 +15,26 @@ use std::{
     path::PathBuf
 };
 
+pub struct UnixSocket {
+    pub path: PathBuf,
+    pub mode: Option<u32>,
+    pub protocol: SocketProtocol
+}
+
 pub struct InitResult {
-    pub host: String,
+    pub host: Option<String>,
+    pub tcp_host: Option<String>,
+    pub unix_socket: Option<UnixSocket>,
     pub store: Store,
+    pub idempotency_location: Option<PathBuf>,
+    /// The directory the named stores keep their databases in
+    pub stores_location: Option<PathBuf>,
     pub store_config: StoreConfig,
     pub config_location: Option<PathBuf>,
-    pub update_config: bool
+    pub update_config: bool,
+    /// The key file to re-encrypt the store with before exiting, given to the `rotate-key` command
+    #[cfg(feature = "level")]
+    pub rotate_key: Option<PathBuf>
 }
 
 fn get_app<'a>() -> App<'a, 'a> {
@@ -36,6 +52,35 @@ fn get_app<'a>() -> App<'a, 'a> {
             .long("port")
             .value_name("PORT")
             .help("Sets the port number"))
+        .arg(Arg::with_name("tcp-host")
+            .long("tcp-host")
+            .value_name("TCP_HOST")
+            .requires("tcp-port")
+            .help("Sets the host address of the tcp packet listener"))
+        .arg(Arg::with_name("tcp-port")
+            .long("tcp-port")
+            .value_name("TCP_PORT")
+            .help("Starts a tcp packet listener on the port number"))
+        .arg(Arg::with_name("no-http")
+            .long("no-http")
+            .requires("unix-socket")
+            .help("Will not serve the http api on the host address and port"))
+        .arg(Arg::with_name("unix-socket")
+            .long("unix-socket")
+            .value_name("PATH")
+            .help("Serves on a unix domain socket at the path"))
+        .arg(Arg::with_name("unix-socket-mode")
+            .long("unix-socket-mode")
+            .value_name("MODE")
+            .requires("unix-socket")
+            .validator(|mode| parse_socket_mode(&mode).map(|_mode| ()))
+            .help("Sets the octal file permissions of the unix domain socket, e.g. 660"))
+        .arg(Arg::with_name("unix-socket-protocol")
+            .long("unix-socket-protocol")
+            .value_name("PROTOCOL")
+            .possible_values(&["http", "packet"])
+            .requires("unix-socket")
+            .help("Sets what the unix domain socket speaks, defaults to http"))
         .arg(Arg::with_name("config")
             .short("c")
             .long("config")
@@ -82,6 +127,82 @@ fn get_host(matches: &ArgMatches, store_config: &StoreConfig) -> String {
     host
 }
 
+fn get_http_host(matches: &ArgMatches, store_config: &StoreConfig) -> Option<String> {
+    if matches.is_present("no-http") || store_config.no_http.unwrap_or(false) {
+        None
+    } else {
+        Some(get_host(matches, store_config))
+    }
+}
+
+/// Exits unless the http api is served somewhere, either on a host or on a unix socket.
+///
+/// Packets alone do not cover the whole api, so a setup without the http api is not left running.
+fn check_http_listener(host: &Option<String>, unix_socket: &Option<UnixSocket>) {
+    let serves_http = host.is_some() || unix_socket.as_ref().is_some_and(|unix_socket| unix_socket.protocol == SocketProtocol::Http);
+    if !serves_http {
+        Error::with_description("Nothing serves the http api, leave out --no-http or give a unix socket with the http protocol", ErrorKind::ArgumentConflict).exit();
+    }
+}
+
+fn parse_socket_mode(mode: &str) -> Result<u32, String> {
+    u32::from_str_radix(mode, 8).ok()
+        .filter(|mode| *mode <= 0o7777)
+        .ok_or_else(|| format!("Unix socket mode must be an octal number such as 660, not {}", mode))
+}
+
+fn get_unix_socket(matches: &ArgMatches, store_config: &StoreConfig) -> Option<UnixSocket> {
+    let unix_socket_config = store_config.unix_socket.clone();
+    let path = {
+        if let Some(path) = matches.value_of("unix-socket") {
+            PathBuf::from(path)
+        } else if let Some(unix_socket_config) = &unix_socket_config {
+            unix_socket_config.path.clone()
+        } else {
+            return None;
+        }
+    };
+    let mode = {
+        if let Some(mode) = matches.value_of("unix-socket-mode") {
+            Some(mode.to_string())
+        } else {
+            unix_socket_config.as_ref().and_then(|unix_socket_config| unix_socket_config.mode.clone())
+        }
+    };
+    let mode = mode.map(|mode| parse_socket_mode(&mode).unwrap_or_else(|error| Error::with_description(&error, ErrorKind::InvalidValue).exit()));
+    let protocol = match matches.value_of("unix-socket-protocol") {
+        Some("packet") => SocketProtocol::Packet,
+        Some(_) => SocketProtocol::Http,
+        None => unix_socket_config.and_then(|unix_socket_config| unix_socket_config.protocol).unwrap_or(SocketProtocol::Http)
+    };
+    Some(UnixSocket { path, mode, protocol })
+}
+
+fn get_tcp_host(matches: &ArgMatches, store_config: &StoreConfig) -> Option<String> {
+    let tcp_config = store_config.tcp.clone();
+    let port = {
+        if let Some(port) = matches.value_of("tcp-port") {
+            port.to_string()
+        } else if let Some(tcp_config) = &tcp_config {
+            tcp_config.port.to_string()
+        } else {
+            return None;
+        }
+    };
+    let mut host = {
+        if let Some(host) = matches.value_of("tcp-host") {
+            host.to_string()
+        } else if let Some(host) = tcp_config.and_then(|tcp_config| tcp_config.host) {
+            host
+        } else {
+            String::from("localhost")
+        }
+    };
+    host.push(':');
+    host.push_str(port.as_str());
+    Some(host)
+}
+
 fn get_update_config_setting(matches: &ArgMatches, store_config: &StoreConfig) -> bool {
     if let Some(noupdate) = store_config.no_update {
         noupdate
@@ -122,7 +243,7 @@ fn get_config_path(matches: &ArgMatches) -> Option<PathBuf> {
 cfg_if::cfg_if! {
     if #[cfg(feature = "mem")] {
 
-        use crate::config::StoreConfig;
+        use crate::config::{SocketProtocol, StoreConfig};
         use msg_store::{ MemStore, open };
         pub type Store = MemStore;
 
@@ -132,10 +253,17 @@ cfg_if::cfg_if! {
             let config_location = get_config_path(&matches);
             let store_config = get_store_config(&config_location);
             let update_config = get_update_config_setting(&matches, &store_config);
+            let host = get_http_host(&matches, &store_config);
+            let unix_socket = get_unix_socket(&matches, &store_config);
+            check_http_listener(&host, &unix_socket);
 
             InitResult {
-                host: get_host(&matches, &store_config),
+                host,
+                tcp_host: get_tcp_host(&matches, &store_config),
+                unix_socket,
                 store: open(),
+                idempotency_location: None,
+                stores_location: None,
                 store_config,
                 config_location,
                 update_config
@@ -143,9 +271,14 @@ cfg_if::cfg_if! {
 
         }
 
+        /// Opens a named store, returning it along with where its idempotency keys are kept.
+        pub fn open_store(_stores_location: &Option<PathBuf>, _name: &str) -> Result<(Store, Option<PathBuf>), String> {
+            Ok((open(), None))
+        }
+
     } else if #[cfg(feature = "level")] {
 
-        use crate::config::StoreConfig;
+        use crate::config::{SocketProtocol, StoreConfig};
         use msg_store_plugin_leveldb::{LevelStore, open};
         use std::fs::create_dir_all;
         // use std::path::Path;
@@ -159,6 +292,11 @@ cfg_if::cfg_if! {
                     .long("leveldb-location")
                     .help("Sets the database location")
                     .takes_value(true))
+                .subcommand(SubCommand::with_name("rotate-key")
+                    .about("Re-encrypts the stored messages with the key in the file, saves it to the config and exits")
+                    .arg(Arg::with_name("KEY_FILE")
+                        .required(true)
+                        .help("The file holding the new key")))
                 .get_matches();
 
             let config_location = get_config_path(&matches);
@@ -188,16 +326,47 @@ cfg_if::cfg_if! {
                 create_dir_all(leveldb_location.clone()).expect("Could not create leveldb location");
             }
             
+            let rotate_key = matches.subcommand_matches("rotate-key")
+                .and_then(|rotate_key| rotate_key.value_of("KEY_FILE"))
+                .map(PathBuf::from);
+            let host = get_http_host(&matches, &store_config);
+            let unix_socket = get_unix_socket(&matches, &store_config);
+            // rotating the key exits before anything is served
+            if rotate_key.is_none() {
+                check_http_listener(&host, &unix_socket);
+            }
+
             InitResult {
-                host: get_host(&matches, &store_config),
+                host,
+                tcp_host: get_tcp_host(&matches, &store_config),
+                unix_socket,
                 store: open(leveldb_location.as_path()).unwrap(),
+                idempotency_location: Some(leveldb_location.join("idempotency-keys")),
+                stores_location: Some(leveldb_location.join("stores")),
                 store_config,
                 config_location,
-                update_config
+                update_config,
+                rotate_key
             }
 
         }
 
+        /// Opens a named store in its own directory under the stores location,
+        /// returning it along with where its idempotency keys are kept.
+        pub fn open_store(stores_location: &Option<PathBuf>, name: &str) -> Result<(Store, Option<PathBuf>), String> {
+            let location = match stores_location {
+                Some(stores_location) => stores_location.join(name),
+                None => {
+                    return Err("Leveldb location is not set.".to_string());
+                }
+            };
+            if !location.exists() {
+                create_dir_all(location.clone()).map_err(|error| error.to_string())?;
+            }
+            let store = open(location.as_path()).map_err(|error| format!("{:?}", error))?;
+            Ok((store, Some(location.join("idempotency-keys"))))
+        }
+
     } else {
         fn foo() { /* fallback implementation */ }
     }
@@ -0,0 +1,255 @@
+use actix_web::{
+    rt::time::interval,
+    web::Data
+};
+use crate::{
+    api::msg::release,
+    AppData
+};
+use msg_store::Uuid;
+use std::{
+    collections::BTreeMap,
+    fmt,
+    time::{
+        Duration,
+        Instant
+    }
+};
+
+const REAP_INTERVAL: Duration = Duration::from_secs(1);
+const TOKEN_SIZE: usize = 16;
+
+/// Names a lease. It is made of random bytes, written out as hex, so it cannot be worked out
+/// from the message uuid or the time of the claim.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Token([u8; TOKEN_SIZE]);
+
+impl Token {
+    pub fn new() -> Result<Token, getrandom::Error> {
+        let mut bytes = [0u8; TOKEN_SIZE];
+        getrandom::getrandom(&mut bytes)?;
+        Ok(Token(bytes))
+    }
+
+    pub fn parse(token: &str) -> Option<Token> {
+        if token.len() != TOKEN_SIZE * 2 || !token.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            return None;
+        }
+        let mut bytes = [0u8; TOKEN_SIZE];
+        for (index, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&token[index * 2..index * 2 + 2], 16).ok()?;
+        }
+        Some(Token(bytes))
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.0.iter() {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+/// A consumer's claim on a message, identified by its lease token.
+pub struct Lease {
+    pub uuid: Uuid,
+    /// The consumer group the message was claimed for, if the store fans out
+    pub consumer_group: Option<String>,
+    pub expires_at: Instant
+}
+
+/// A message as handed to one consumer group, or to every consumer if the store does not fan out.
+type Claimant = (Uuid, Option<String>);
+
+fn claimant(uuid: &Uuid, consumer_group: Option<&str>) -> Claimant {
+    (*uuid, consumer_group.map(String::from))
+}
+
+/// Keeps track of which messages consumers have claimed and how often each was handed out.
+///
+/// A claimed message stays in the store and keeps counting toward the byte limits,
+/// it is only hidden from other consumers until the lease is acked, nacked or runs out.
+/// In a store that fans out every consumer group claims its messages on its own.
+pub struct Leases {
+    leases: BTreeMap<Token, Lease>,
+    tokens: BTreeMap<Claimant, Token>,
+    attempts: BTreeMap<Claimant, u32>
+}
+
+impl Leases {
+    pub fn new() -> Leases {
+        Leases {
+            leases: BTreeMap::new(),
+            tokens: BTreeMap::new(),
+            attempts: BTreeMap::new()
+        }
+    }
+
+    pub fn is_claimed(&self, uuid: &Uuid, consumer_group: Option<&str>, now: Instant) -> bool {
+        self.tokens.get(&claimant(uuid, consumer_group))
+            .and_then(|token| self.leases.get(token))
+            .is_some_and(|lease| lease.expires_at > now)
+    }
+
+    /// The number of times the message has been claimed.
+    pub fn attempts(&self, uuid: &Uuid, consumer_group: Option<&str>) -> u32 {
+        self.attempts.get(&claimant(uuid, consumer_group)).copied().unwrap_or(0)
+    }
+
+    /// Hands a message out under a new lease token and counts the delivery attempt.
+    ///
+    /// Returns the attempt count including this one.
+    pub fn claim(&mut self, uuid: Uuid, consumer_group: Option<&str>, token: Token, visibility_timeout: Duration) -> u32 {
+        let key = claimant(&uuid, consumer_group);
+        if let Some(previous_token) = self.tokens.insert(key.clone(), token) {
+            self.leases.remove(&previous_token);
+        }
+        self.leases.insert(token, Lease {
+            uuid,
+            consumer_group: consumer_group.map(String::from),
+            expires_at: Instant::now() + visibility_timeout
+        });
+        let attempts = self.attempts.entry(key).or_insert(0);
+        *attempts += 1;
+        *attempts
+    }
+
+    /// Ends a lease that has not run out yet.
+    pub fn release(&mut self, token: &Token, now: Instant) -> Option<Lease> {
+        if self.leases.get(token)?.expires_at <= now {
+            return None;
+        }
+        let lease = self.leases.remove(token)?;
+        self.tokens.remove(&claimant(&lease.uuid, lease.consumer_group.as_deref()));
+        Some(lease)
+    }
+
+    /// Drops the leases that have run out and returns them.
+    pub fn expire(&mut self, now: Instant) -> Vec<Lease> {
+        let expired_tokens: Vec<Token> = self.leases.iter()
+            .filter(|(_token, lease)| lease.expires_at <= now)
+            .map(|(token, _lease)| *token)
+            .collect();
+        let mut expired = vec![];
+        for token in expired_tokens {
+            if let Some(lease) = self.leases.remove(&token) {
+                self.tokens.remove(&claimant(&lease.uuid, lease.consumer_group.as_deref()));
+                expired.push(lease);
+            }
+        }
+        expired
+    }
+
+    /// Whether any lease has run out, without taking it.
+    pub fn has_expired(&self, now: Instant) -> bool {
+        self.leases.values().any(|lease| lease.expires_at <= now)
+    }
+
+    /// Forgets a message that has left the store, for every consumer group.
+    pub fn forget(&mut self, uuid: &Uuid) {
+        // the claimants of a message sort next to each other, starting with the one without a consumer group
+        let first = (*uuid, None);
+        let claimants: Vec<Claimant> = self.attempts.range(first.clone()..).map(|(key, _attempts)| key)
+            .take_while(|(claimed, _consumer_group)| claimed == uuid)
+            .chain(self.tokens.range(first..).map(|(key, _token)| key).take_while(|(claimed, _consumer_group)| claimed == uuid))
+            .cloned()
+            .collect();
+        for key in claimants {
+            if let Some(token) = self.tokens.remove(&key) {
+                self.leases.remove(&token);
+            }
+            self.attempts.remove(&key);
+        }
+    }
+}
+
+/// Periodically returns messages with expired leases to their groups and lets listeners know they are available again,
+/// or sets them aside as dead letters once they have used up their delivery attempts.
+///
+/// Expired leases stop hiding their message right away, this makes sure waiting consumers hear about it.
+/// The store is only locked once there is a lease to reap.
+pub async fn reap(data: Data<AppData>) {
+    let mut ticks = interval(REAP_INTERVAL);
+    loop {
+        ticks.tick().await;
+        match data.leases.try_lock() {
+            Ok(leases) if leases.has_expired(Instant::now()) => (),
+            _ => continue
+        }
+        let mut store = match data.store.try_lock() {
+            Ok(store) => store,
+            Err(_error) => continue
+        };
+        let expired = match data.leases.try_lock() {
+            Ok(mut leases) => leases.expire(Instant::now()),
+            Err(_error) => continue
+        };
+        for lease in expired {
+            if release(&data, &mut store, &lease.uuid, lease.consumer_group.as_deref()).is_err() {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uuid(timestamp: u128) -> Uuid {
+        Uuid { timestamp, sequence: 1 }
+    }
+
+    fn claim(leases: &mut Leases, uuid: Uuid, consumer_group: Option<&str>) -> Token {
+        let token = Token::new().unwrap();
+        leases.claim(uuid, consumer_group, token, Duration::from_secs(30));
+        token
+    }
+
+    #[test]
+    fn forget_drops_every_consumer_group() {
+        let mut leases = Leases::new();
+        let now = Instant::now();
+        let tokens = [
+            claim(&mut leases, uuid(2), None),
+            claim(&mut leases, uuid(2), Some("audit")),
+            claim(&mut leases, uuid(2), Some("billing"))
+        ];
+        let kept = claim(&mut leases, uuid(3), Some("audit"));
+        claim(&mut leases, uuid(1), Some("billing"));
+        leases.forget(&uuid(2));
+        for consumer_group in [None, Some("audit"), Some("billing")] {
+            assert!(!leases.is_claimed(&uuid(2), consumer_group, now));
+            assert_eq!(leases.attempts(&uuid(2), consumer_group), 0);
+        }
+        for token in tokens.iter() {
+            assert!(leases.release(token, now).is_none());
+        }
+        assert!(leases.is_claimed(&uuid(1), Some("billing"), now));
+        assert_eq!(leases.attempts(&uuid(3), Some("audit")), 1);
+        assert!(leases.release(&kept, now).is_some());
+    }
+
+    #[test]
+    fn expired_leases_are_found_before_they_are_taken() {
+        let mut leases = Leases::new();
+        let now = Instant::now();
+        claim(&mut leases, uuid(1), None);
+        assert!(!leases.has_expired(now));
+        let later = now + Duration::from_secs(31);
+        assert!(leases.has_expired(later));
+        assert_eq!(leases.expire(later).len(), 1);
+        assert!(!leases.has_expired(later));
+    }
+
+    #[test]
+    fn token_round_trips_through_text() {
+        let token = Token::new().unwrap();
+        assert_eq!(token.to_string().len(), TOKEN_SIZE * 2);
+        assert_eq!(Token::parse(&token.to_string()), Some(token));
+        assert_eq!(Token::parse("1638909095441178346-1"), None);
+        assert_eq!(Token::parse(&format!("+{}", &token.to_string()[1..])), None);
+    }
+}
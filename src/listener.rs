@@ -0,0 +1,188 @@
+#[cfg(unix)]
+use actix_web::rt::net::UnixListener;
+use actix_web::{
+    rt::{
+        net::TcpListener,
+        spawn,
+        time::delay_for
+    },
+    web::Data
+};
+use crate::{
+    AppData,
+    msg::handle
+};
+use serde_json::to_string;
+use std::{
+    io,
+    str,
+    time::Duration
+};
+#[cfg(unix)]
+use std::{
+    fs::{
+        self,
+        DirBuilder,
+        Permissions
+    },
+    os::unix::{
+        fs::{
+            DirBuilderExt,
+            FileTypeExt,
+            PermissionsExt
+        },
+        net::{
+            UnixListener as StdUnixListener,
+            UnixStream as StdUnixStream
+        }
+    },
+    path::Path,
+    process
+};
+use tokio::io::{
+    split,
+    AsyncBufReadExt,
+    AsyncRead,
+    AsyncReadExt,
+    AsyncWrite,
+    AsyncWriteExt,
+    BufReader
+};
+
+/// The longest request packet a client may send, not counting the newline.
+const MAX_PACKET_SIZE: usize = 16 * 1024 * 1024;
+
+/// How long to wait before accepting again after accepting a connection failed,
+/// e.g. because the process is out of file descriptors.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Reads newline delimited request packets from the stream and writes a reply packet,
+/// also newline delimited, for each one until the client hangs up.
+///
+/// A client that sends a packet longer than the max packet size is disconnected.
+pub async fn serve<S>(data: Data<AppData>, stream: S) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite
+{
+    let (reader, mut writer) = split(stream);
+    let mut reader = BufReader::new(reader);
+    let mut packet = Vec::new();
+    loop {
+        packet.clear();
+        // one byte over the max is enough to tell the packet is too long
+        let read = (&mut reader).take(MAX_PACKET_SIZE as u64 + 2).read_until(b'\n', &mut packet).await?;
+        if read == 0 {
+            break;
+        }
+        if packet.last() == Some(&b'\n') {
+            packet.pop();
+        }
+        if packet.last() == Some(&b'\r') {
+            packet.pop();
+        }
+        if packet.len() > MAX_PACKET_SIZE {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Packet is too long"));
+        }
+        let line = str::from_utf8(&packet).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let reply = handle(&data, line);
+        let mut reply = to_string(&reply)?;
+        reply.push('\n');
+        writer.write_all(reply.as_bytes()).await?;
+    }
+    Ok(())
+}
+
+/// Binds the tcp packet listener and serves every connection on the current arbiter.
+pub async fn bind_tcp(data: Data<AppData>, host: &str) -> io::Result<()> {
+    let mut listener = TcpListener::bind(host).await?;
+    spawn(async move {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _address)) => stream,
+                Err(error) => {
+                    log::error!("Could not accept a connection: {}", error);
+                    delay_for(ACCEPT_BACKOFF).await;
+                    continue;
+                }
+            };
+            let data = data.clone();
+            spawn(async move {
+                let _ = serve(data, stream).await;
+            });
+        }
+    });
+    Ok(())
+}
+
+/// Removes a socket file left behind by a previous run so the path can be bound again.
+///
+/// A socket that some process still accepts connections on is left alone and an error is returned.
+/// Anything at the path that is not a socket is left alone and binding will fail instead.
+#[cfg(unix)]
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => match StdUnixStream::connect(path) {
+            Ok(_stream) => Err(io::Error::new(io::ErrorKind::AddrInUse, format!("{} is in use by another process", path.display()))),
+            Err(error) if error.kind() == io::ErrorKind::ConnectionRefused => fs::remove_file(path),
+            Err(error) => Err(error)
+        },
+        _ => Ok(())
+    }
+}
+
+/// Binds a unix domain socket at the path, replacing a stale socket left behind by a previous run.
+///
+/// With a mode, the socket is bound in a directory only this process can enter and given its mode there
+/// before it is linked into place, so it is never reachable with the looser mode it is created with.
+#[cfg(unix)]
+pub fn bind_socket(path: &Path, mode: Option<u32>) -> io::Result<StdUnixListener> {
+    remove_stale_socket(path)?;
+    let mode = match mode {
+        Some(mode) => mode,
+        None => {
+            return StdUnixListener::bind(path);
+        }
+    };
+    let file_name = path.file_name().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Unix socket path has no file name"))?;
+    let parent = path.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or_else(|| Path::new("."));
+    let private_dir = parent.join(format!(".{}.{}", file_name.to_string_lossy(), process::id()));
+    DirBuilder::new().mode(0o700).create(&private_dir)?;
+    let private_path = private_dir.join(file_name);
+    let listener = StdUnixListener::bind(&private_path).and_then(|listener| {
+        fs::set_permissions(&private_path, Permissions::from_mode(mode))?;
+        // unlike a rename, linking fails instead of replacing whatever is at the path by now
+        fs::hard_link(&private_path, path)?;
+        Ok(listener)
+    });
+    let _ = fs::remove_file(&private_path);
+    let _ = fs::remove_dir(&private_dir);
+    listener
+}
+
+/// Binds the packet protocol to a unix domain socket and serves every connection on the current arbiter.
+#[cfg(unix)]
+pub fn bind_unix(data: Data<AppData>, path: &Path, mode: Option<u32>) -> io::Result<()> {
+    let listener = bind_socket(path, mode)?;
+    listener.set_nonblocking(true)?;
+    let mut listener = UnixListener::from_std(listener)?;
+    spawn(async move {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _address)) => stream,
+                Err(error) => {
+                    log::error!("Could not accept a connection: {}", error);
+                    delay_for(ACCEPT_BACKOFF).await;
+                    continue;
+                }
+            };
+            let data = data.clone();
+            spawn(async move {
+                let _ = serve(data, stream).await;
+            });
+        }
+    });
+    Ok(())
+}
@@ -1,6 +1,7 @@
 use std::{
     path::PathBuf,
     sync::{
+        Arc,
         Mutex,
         // MutexGuard
     }};
@@ -8,74 +9,269 @@ use actix_web::{
     middleware, 
     web::{
         self,
-        Data
+        Data,
+        ServiceConfig
     }, 
     App, 
     HttpServer
 };
 
 mod api;
+mod compression;
 mod config;
+#[cfg(feature = "level")]
+mod encryption;
+mod events;
+mod idempotency;
 mod init;
+mod leases;
+mod listener;
+mod meta;
+mod msg;
+mod schedule;
+mod stores;
 
+use api::msg::Aging;
+
+#[cfg(unix)]
+use config::SocketProtocol;
 use config::{
-    StoreConfig
+    ConfigFile,
+    StoreSettings
 };
 
+#[cfg(feature = "level")]
+use encryption::Cipher;
+
+use events::Events;
+
+use idempotency::Idempotency;
+
+use leases::Leases;
+
+use meta::MetaIndex;
+
+use schedule::Schedule;
+
 use init::{
     Store,
     init
 };
 
+use msg_store::store::{
+    GroupDefaults,
+    StoreDefaults
+};
+
+use stores::{
+    start,
+    Stores,
+    valid_name
+};
+
 // pub type StoreGaurd<'a> = MutexGuard<'a, Store>;
 // pub type ConfigGaurd<'a> = MutexGuard<'a, StoreConfig>;
 
 pub struct AppData {
+    /// The name of a named store, None for the default store
+    pub name: Option<String>,
     pub store: Mutex<Store>,
-    pub config_location: Option<PathBuf>,
-    pub config: Mutex<StoreConfig>
+    pub config_file: Arc<ConfigFile>,
+    pub config: Mutex<StoreSettings>,
+    pub events: Events,
+    pub leases: Mutex<Leases>,
+    pub meta: Mutex<MetaIndex>,
+    pub idempotency: Mutex<Idempotency>,
+    pub schedule: Mutex<Schedule>,
+    /// How messages are aged, checked when the store is opened
+    pub aging: Option<Aging>,
+    #[cfg(feature = "level")]
+    pub cipher: Option<Cipher>
+}
+
+impl AppData {
+    /// Applies the store and group limits of the settings to the store and loads what is kept beside its messages.
+    pub fn open(
+        name: Option<String>,
+        mut store: Store,
+        idempotency_location: Option<PathBuf>,
+        settings: StoreSettings,
+        config_file: Arc<ConfigFile>,
+        #[cfg(feature = "level")]
+        cipher: Option<Cipher>
+    ) -> Result<AppData, String> {
+        let aging = settings.aging.as_ref().map(Aging::new).transpose()?;
+        store.update_store_defaults(&StoreDefaults { max_byte_size: settings.max_byte_size })
+            .map_err(|error| format!("Could not apply the store defaults: {:?}", error))?;
+        for group in settings.groups.iter().flatten() {
+            store.update_group_defaults(group.priority, &GroupDefaults { max_byte_size: group.max_byte_size })
+                .map_err(|error| format!("Could not apply the group defaults: {:?}", error))?;
+        }
+        let meta = MetaIndex::load(&mut store)
+            .map_err(|error| format!("Could not read the meta data of stored messages: {:?}", error))?;
+        let idempotency = Idempotency::open(idempotency_location, &settings, meta::now_millis())
+            .map_err(|error| format!("Could not load the idempotency keys: {}", error))?;
+        let schedule = Schedule::new(settings.fair_dequeue.as_ref());
+        Ok(AppData {
+            name,
+            store: Mutex::new(store),
+            config_file,
+            config: Mutex::new(settings),
+            events: Events::new(),
+            leases: Mutex::new(Leases::new()),
+            meta: Mutex::new(meta),
+            idempotency: Mutex::new(idempotency),
+            schedule: Mutex::new(schedule),
+            aging,
+            #[cfg(feature = "level")]
+            cipher
+        })
+    }
+
+    /// An in memory store with the given settings and no config file to save them to.
+    #[cfg(all(test, feature = "mem"))]
+    pub fn in_memory(settings: StoreSettings) -> AppData {
+        let config_file = Arc::new(ConfigFile { location: None, config: Mutex::new(config::StoreConfig::new()) });
+        AppData::open(None, msg_store::open(), None, settings, config_file).expect("Could not open the store")
+    }
+}
+
+/// The most bytes a batch of messages posted to `/msgs` may take up unless the config sets `max_msgs_body_size`
+const DEFAULT_MAX_MSGS_BODY_SIZE: usize = 16 * 1024 * 1024;
+
+/// The routes every store answers, both at `/api` for the default store and at `/api/stores/{name}` for the named ones.
+fn store_routes(config: &mut ServiceConfig, max_msgs_body_size: usize) {
+    config
+        .route("/ack", web::post().to(api::ack::post))
+
+        .route("/dead-letters", web::delete().to(api::dead_letters::delete::delete))
+        .route("/dead-letters", web::get().to(api::dead_letters::get::get))
+        .route("/dead-letters", web::put().to(api::dead_letters::put::update))
+
+        .route("/events", web::get().to(api::events::get))
+
+        .route("/export", web::get().to(api::export::get))
+        
+        .route("/group", web::delete().to(api::group::delete::delete))
+        .route("/group", web::get().to(api::group::get::get))
+        
+        .route("/group-defaults", web::delete().to(api::group_defaults::delete::delete))
+        .route("/group-defaults", web::get().to(api::group_defaults::get::get))
+        .route("/group-defaults", web::post().to(api::group_defaults::post::post))
+        
+        .route("/msg", web::get().to(api::msg::get::get))
+        .route("/msg", web::delete().to(api::msg::delete::delete))
+        .route("/msg", web::post().to(api::msg::post::post))
+        .route("/msg", web::put().to(api::msg::put::update))
+
+        .route("/msg/raw", web::get().to(api::msg::raw::get))
+        .route("/msg/raw", web::post().to(api::msg::raw::post))
+
+        .service(web::resource("/msgs")
+            .app_data(web::JsonConfig::default().limit(max_msgs_body_size))
+            .route(web::delete().to(api::msgs::delete::delete))
+            .route(web::get().to(api::msgs::get::get))
+            .route(web::post().to(api::msgs::post::post)))
+        
+        .route("/nack", web::post().to(api::nack::post))
+
+        .route("/stats", web::delete().to(api::stats::delete::delete))
+        .route("/stats", web::get().to(api::stats::get::get))
+        .route("/stats", web::put().to(api::stats::put::update))
+
+        .route("/store", web::get().to(api::store::get::get))
+        .route("/store", web::put().to(api::store::put::update))
+
+        .route("/ws", web::get().to(api::ws::ws));
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     std::env::set_var("RUST_LOG", "actix_server=info,actix_web=info");
 
-    let init_result = init();
+    #[cfg_attr(not(feature = "level"), allow(unused_mut))]
+    let mut init_result = init();
 
-    let app_data = Data::new(AppData {
-        store: Mutex::new(init_result.store),
-        config_location: init_result.config_location,
+    #[cfg(feature = "level")]
+    let cipher = encryption::load(&init_result.store_config.encryption).expect("Could not load the encryption key");
+
+    #[cfg(feature = "level")]
+    if let Some(key_file) = init_result.rotate_key.take() {
+        return encryption::run_rotate_key(&mut init_result, cipher.as_ref(), key_file);
+    }
+
+    let max_msgs_body_size = init_result.store_config.max_msgs_body_size.unwrap_or(DEFAULT_MAX_MSGS_BODY_SIZE);
+    let settings = init_result.store_config.settings.clone();
+    let named_settings = init_result.store_config.stores.clone().unwrap_or_default();
+
+    let config_file = Arc::new(ConfigFile {
+        location: init_result.config_location,
         config: Mutex::new(init_result.store_config)
     });
 
-    HttpServer::new(move || {
+    let app_data = Data::new(AppData::open(
+        None,
+        init_result.store,
+        init_result.idempotency_location,
+        settings,
+        config_file,
+        #[cfg(feature = "level")]
+        cipher
+    ).expect("Could not open the store"));
+
+    let stores = Data::new(Stores::new(app_data.clone(), init_result.stores_location));
+    for (name, settings) in named_settings {
+        if !valid_name(&name) {
+            panic!("Invalid store name: {}", name);
+        }
+        let named_data = stores.open(&name, settings).expect("Could not open a named store");
+        start(&named_data);
+        stores.named.write().expect("Could not lock the stores").insert(name, named_data);
+    }
+
+    start(&app_data);
+
+    if let Some(tcp_host) = init_result.tcp_host {
+        listener::bind_tcp(app_data.clone(), &tcp_host).await?;
+    }
+
+    #[cfg(not(unix))]
+    if init_result.unix_socket.is_some() {
+        panic!("Unix domain sockets are only supported on unix platforms");
+    }
+
+    #[cfg(unix)]
+    if let Some(unix_socket) = &init_result.unix_socket {
+        if unix_socket.protocol == SocketProtocol::Packet {
+            listener::bind_unix(app_data.clone(), &unix_socket.path, unix_socket.mode)?;
+        }
+    }
+
+    let mut server = HttpServer::new(move || {
         App::new()
             // enable logger
             .wrap(middleware::Logger::default())
-            .app_data(app_data.clone())
-
-            .route("/api/export", web::get().to(api::export::get))
-            
-            .route("/api/group", web::delete().to(api::group::delete::delete))
-            .route("/api/group", web::get().to(api::group::get::get))
-            
-            .route("/api/group-defaults", web::delete().to(api::group_defaults::delete::delete))
-            .route("/api/group-defaults", web::get().to(api::group_defaults::get::get))
-            .route("/api/group-defaults", web::post().to(api::group_defaults::post::post))
-            
-            .route("/api/msg", web::get().to(api::msg::get::get))
-            .route("/api/msg", web::delete().to(api::msg::delete::delete))
-            .route("/api/msg", web::post().to(api::msg::post::post))
-            
-            .route("/api/stats", web::delete().to(api::stats::delete::delete))
-            .route("/api/stats", web::get().to(api::stats::get::get))
-            .route("/api/stats", web::put().to(api::stats::put::update))
-
-            .route("/api/store", web::get().to(api::store::get::get))
-            .route("/api/store", web::put().to(api::store::put::update))
-    })
+            .app_data(stores.clone())
+
+            .service(web::scope("/api/stores/{name}").configure(|config| store_routes(config, max_msgs_body_size)))
+            .service(web::scope("/api")
+                .route("/stores", web::get().to(api::stores::get::get))
+                .route("/stores", web::post().to(api::stores::post::post))
+                .configure(|config| store_routes(config, max_msgs_body_size)))
+    });
+
     // start http server on 127.0.0.1:8080
-    .bind(init_result.host)?
-    .run()
-    .await
+    if let Some(host) = init_result.host {
+        server = server.bind(host)?;
+    }
+
+    #[cfg(unix)]
+    if let Some(unix_socket) = &init_result.unix_socket {
+        if unix_socket.protocol == SocketProtocol::Http {
+            server = server.listen_uds(listener::bind_socket(&unix_socket.path, unix_socket.mode)?)?;
+        }
+    }
+
+    // init makes sure the http api is served on the host or the unix socket
+    server.run().await
 }
@@ -0,0 +1,409 @@
+use actix_web::{
+    rt::time::interval,
+    web::Data
+};
+use crate::{
+    api::msg::{
+        expire,
+        msg_event
+    },
+    AppData,
+    config::CompressionAlgorithm,
+    events::EventKind,
+    init::Store
+};
+use msg_store::{
+    errors::Error,
+    GetOptions,
+    Uuid
+};
+use serde::{
+    Deserialize,
+    Serialize
+};
+use serde_json::{
+    from_str,
+    to_string,
+    Value
+};
+use std::{
+    collections::{
+        BTreeMap,
+        BTreeSet
+    },
+    time::{
+        Duration,
+        SystemTime,
+        UNIX_EPOCH
+    }
+};
+
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Free form attributes a producer attaches to a message.
+pub type Headers = BTreeMap<String, String>;
+
+/// Marks a stored body that starts with meta data rather than the message itself.
+const MARKER: &str = "\u{0}msg-store-meta\u{0}";
+
+/// Server side details kept with a message.
+///
+/// They are written to the database in front of the message body so they survive restarts.
+/// Messages without any are stored as the bare body, the same as they always were.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Meta {
+    /// Milliseconds since the unix epoch after which the message is deleted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+    /// Milliseconds since the unix epoch before which the message is held back from consumers
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub not_before: Option<u64>,
+    /// Set once the message has failed delivery too many times
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dead_letter: Option<DeadLetter>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: Headers,
+    /// The message is raw bytes kept base64 encoded
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub binary: bool,
+    /// The message is JSON text that was sent as a value other than a string
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub json: bool,
+    /// The message is compressed and kept base64 encoded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compressed: Option<CompressionAlgorithm>,
+    /// The message is encrypted with the configured key
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub encrypted: bool,
+    /// The consumer groups that have acknowledged the message
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub acked_by: BTreeSet<String>
+}
+
+pub fn is_false(value: &bool) -> bool {
+    !value
+}
+
+/// Where a dead letter came from and how often delivery was attempted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeadLetter {
+    pub priority: u32,
+    pub attempts: u32
+}
+
+impl Meta {
+    pub fn is_empty(&self) -> bool {
+        *self == Meta::default()
+    }
+}
+
+/// Milliseconds since the unix epoch.
+pub fn now_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|since| since.as_millis() as u64).unwrap_or(0)
+}
+
+/// The size of the message that counts toward the byte limits, the decoded size for raw bytes.
+pub fn byte_size(meta: &Meta, msg: &str) -> u32 {
+    if !meta.binary {
+        return msg.len() as u32;
+    }
+    let padding = msg.bytes().rev().take_while(|byte| *byte == b'=').count();
+    (msg.len() / 4 * 3).saturating_sub(padding) as u32
+}
+
+/// The text a message given as any JSON value is stored as, strings are kept as they are.
+pub fn msg_text(msg: &Value) -> String {
+    match msg {
+        Value::String(msg) => msg.clone(),
+        msg => msg.to_string()
+    }
+}
+
+/// The message as it was given, parsing it back into JSON if it was not a string.
+pub fn msg_value(meta: &Meta, msg: String) -> Value {
+    if !meta.json {
+        return Value::String(msg);
+    }
+    match from_str(&msg) {
+        Ok(value) => value,
+        Err(_error) => Value::String(msg)
+    }
+}
+
+/// Builds the body that is written to the database.
+///
+/// A message that itself starts with the marker always gets a header, even an empty one, so its
+/// bytes are never read back as meta data.
+pub fn encode(meta: &Meta, msg: String) -> String {
+    if meta.is_empty() && !msg.starts_with(MARKER) {
+        return msg;
+    }
+    match to_string(meta) {
+        Ok(json) => format!("{}{}\n{}", MARKER, json, msg),
+        Err(_error) => msg
+    }
+}
+
+/// Splits a body read from the database into its meta data and the message.
+pub fn decode(body: String) -> (Meta, String) {
+    if let Some(rest) = body.strip_prefix(MARKER) {
+        if let Some((json, msg)) = rest.split_once('\n') {
+            if let Ok(meta) = from_str(json) {
+                return (meta, msg.to_string());
+            }
+        }
+    }
+    (Meta::default(), body)
+}
+
+/// Keeps the meta data of stored messages in memory so it can be checked without reading the database.
+///
+/// Only messages that have meta data are listed.
+pub struct MetaIndex {
+    msgs: BTreeMap<Uuid, Meta>,
+    /// Database sizes of the messages that take up more or less room than their byte size
+    stored_sizes: BTreeMap<Uuid, u32>,
+    pub msgs_expired: u32,
+    pub msgs_dead_lettered: u32
+}
+
+impl MetaIndex {
+    pub fn new() -> MetaIndex {
+        MetaIndex {
+            msgs: BTreeMap::new(),
+            stored_sizes: BTreeMap::new(),
+            msgs_expired: 0,
+            msgs_dead_lettered: 0
+        }
+    }
+
+    /// Rebuilds the index from the messages already in the store.
+    pub fn load(store: &mut Store) -> Result<MetaIndex, Error> {
+        let mut index = MetaIndex::new();
+        let uuids: Vec<Uuid> = store.id_to_group_map.keys().copied().collect();
+        for uuid in uuids {
+            if let Some(stored_packet) = store.get(GetOptions::default().uuid(uuid))? {
+                let stored_size = stored_packet.msg.len() as u32;
+                let (meta, _msg) = decode(stored_packet.msg);
+                index.insert(uuid, meta);
+                if let Some(msg) = msg_event(store, &uuid) {
+                    index.set_stored_size(uuid, msg.byte_size, stored_size);
+                }
+            }
+        }
+        Ok(index)
+    }
+
+    /// Sets the meta data of a message, replacing what was there.
+    pub fn insert(&mut self, uuid: Uuid, meta: Meta) {
+        if meta.is_empty() {
+            self.msgs.remove(&uuid);
+        } else {
+            self.msgs.insert(uuid, meta);
+        }
+    }
+
+    /// Notes how much room a message takes up in the database.
+    pub fn set_stored_size(&mut self, uuid: Uuid, byte_size: u32, stored_size: u32) {
+        if stored_size == byte_size {
+            self.stored_sizes.remove(&uuid);
+        } else {
+            self.stored_sizes.insert(uuid, stored_size);
+        }
+    }
+
+    /// The room the messages of the store, or of one priority group, take up in the database,
+    /// counting meta data and compression.
+    pub fn stored_byte_size(&self, store: &Store, priority: Option<u32>) -> u32 {
+        let byte_size = match priority {
+            Some(priority) => store.groups_map.get(&priority).map(|group| group.byte_size).unwrap_or(0),
+            None => store.byte_size
+        };
+        let mut stored_byte_size = byte_size as i64;
+        for (uuid, stored_size) in self.stored_sizes.iter() {
+            let msg = match msg_event(store, uuid) {
+                Some(msg) => msg,
+                None => continue
+            };
+            if priority.is_none_or(|priority| priority == msg.priority) {
+                stored_byte_size += *stored_size as i64 - msg.byte_size as i64;
+            }
+        }
+        stored_byte_size.clamp(0, u32::MAX as i64) as u32
+    }
+
+    pub fn get(&self, uuid: &Uuid) -> Meta {
+        self.msgs.get(uuid).cloned().unwrap_or_default()
+    }
+
+    pub fn is_dead(&self, uuid: &Uuid) -> bool {
+        self.msgs.get(uuid).is_some_and(|meta| meta.dead_letter.is_some())
+    }
+
+    pub fn dead_letters(&self) -> Vec<(Uuid, DeadLetter)> {
+        self.msgs.iter()
+            .filter_map(|(uuid, meta)| meta.dead_letter.map(|dead_letter| (*uuid, dead_letter)))
+            .collect()
+    }
+
+    /// Whether the consumer group has already acknowledged the message.
+    pub fn is_acked(&self, uuid: &Uuid, consumer_group: Option<&str>) -> bool {
+        match consumer_group {
+            Some(consumer_group) => self.msgs.get(uuid).is_some_and(|meta| meta.acked_by.contains(consumer_group)),
+            None => false
+        }
+    }
+
+    /// The number of messages the consumer group has acknowledged.
+    pub fn acked_count(&self, consumer_group: &str) -> usize {
+        self.msgs.values().filter(|meta| meta.acked_by.contains(consumer_group)).count()
+    }
+
+    /// Whether the message has every header of the filter with the same value.
+    pub fn matches(&self, uuid: &Uuid, filter: &Headers) -> bool {
+        if filter.is_empty() {
+            return true;
+        }
+        match self.msgs.get(uuid) {
+            Some(meta) => filter.iter().all(|(name, value)| meta.headers.get(name) == Some(value)),
+            None => false
+        }
+    }
+
+    pub fn is_expired(&self, uuid: &Uuid, now: u64) -> bool {
+        self.msgs.get(uuid)
+            .and_then(|meta| meta.expires_at)
+            .is_some_and(|expires_at| expires_at <= now)
+    }
+
+    pub fn is_delayed(&self, uuid: &Uuid, now: u64) -> bool {
+        self.msgs.get(uuid)
+            .and_then(|meta| meta.not_before)
+            .is_some_and(|not_before| not_before > now)
+    }
+
+    /// Messages whose not before time fell after `since` and on or before `now`.
+    pub fn due(&self, since: u64, now: u64) -> Vec<Uuid> {
+        self.msgs.iter()
+            .filter(|(_uuid, meta)| meta.not_before.is_some_and(|not_before| since < not_before && not_before <= now))
+            .map(|(uuid, _meta)| *uuid)
+            .collect()
+    }
+
+    pub fn expired(&self, now: u64) -> Vec<Uuid> {
+        self.msgs.iter()
+            .filter(|(_uuid, meta)| meta.expires_at.is_some_and(|expires_at| expires_at <= now))
+            .map(|(uuid, _meta)| *uuid)
+            .collect()
+    }
+
+    /// Forgets a message that has left the store.
+    pub fn forget(&mut self, uuid: &Uuid) {
+        self.msgs.remove(uuid);
+        self.stored_sizes.remove(uuid);
+    }
+
+    pub fn inc_msgs_expired(&mut self) {
+        if self.msgs_expired == u32::MAX {
+            self.msgs_expired = 1;
+        } else {
+            self.msgs_expired += 1;
+        }
+    }
+
+    pub fn inc_msgs_dead_lettered(&mut self) {
+        if self.msgs_dead_lettered == u32::MAX {
+            self.msgs_dead_lettered = 1;
+        } else {
+            self.msgs_dead_lettered += 1;
+        }
+    }
+}
+
+/// Periodically deletes messages that have outlived their time to live, lets
+/// listeners know about delayed messages that have become available and drops
+/// idempotency keys that have fallen out of their window.
+///
+/// Both take effect for consumers right away, this only frees up space and wakes up waiting consumers.
+/// The store is only locked once there is a message to expire or a delayed message that became available.
+pub async fn sweep(data: Data<AppData>) {
+    let mut ticks = interval(SWEEP_INTERVAL);
+    let mut last_sweep = now_millis();
+    loop {
+        ticks.tick().await;
+        let now = now_millis();
+        if let Ok(mut idempotency) = data.idempotency.try_lock() {
+            let _ = idempotency.prune(now);
+        }
+        let (due, expired) = match data.meta.try_lock() {
+            Ok(meta) => (meta.due(last_sweep, now), meta.expired(now)),
+            Err(_error) => continue
+        };
+        if due.is_empty() && expired.is_empty() {
+            last_sweep = now;
+            continue;
+        }
+        // a busy store is swept on the next tick, along with what became due in between
+        let mut store = match data.store.try_lock() {
+            Ok(store) => store,
+            Err(_error) => continue
+        };
+        last_sweep = now;
+        for uuid in due {
+            if let Some(msg) = msg_event(&store, &uuid) {
+                data.events.publish(EventKind::Due(msg));
+            }
+        }
+        for uuid in expired {
+            if expire(&data, &mut store, &uuid).is_err() {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn due_and_expired_are_found_without_the_store() {
+        let mut index = MetaIndex::new();
+        let uuid = |timestamp: u128| Uuid { timestamp, sequence: 1 };
+        index.insert(uuid(1), Meta { not_before: Some(100), ..Meta::default() });
+        index.insert(uuid(2), Meta { expires_at: Some(200), ..Meta::default() });
+        index.insert(uuid(3), Meta::default());
+        assert!(index.due(0, 99).is_empty());
+        assert_eq!(index.due(99, 150), vec![uuid(1)]);
+        // a sweep that already saw the message become due does not report it again
+        assert!(index.due(150, 250).is_empty());
+        assert!(index.expired(199).is_empty());
+        assert_eq!(index.expired(200), vec![uuid(2)]);
+    }
+
+    #[test]
+    fn marker_in_message_is_not_read_as_meta() {
+        let msg = format!("{}{{\"compressed\":\"gzip\",\"encrypted\":true}}\nhello", MARKER);
+        let body = encode(&Meta::default(), msg.clone());
+        assert_ne!(body, msg);
+        assert_eq!(decode(body), (Meta::default(), msg));
+    }
+
+    #[test]
+    fn byte_size_of_raw_bytes_is_the_decoded_size() {
+        let binary = Meta { binary: true, ..Meta::default() };
+        for bytes in [&b""[..], b"h", b"hi", b"hey", b"hello"] {
+            assert_eq!(byte_size(&binary, &base64::encode(bytes)), bytes.len() as u32);
+        }
+        assert_eq!(byte_size(&Meta::default(), "aGk="), 4);
+    }
+
+    #[test]
+    fn bare_message_is_stored_as_is() {
+        let body = encode(&Meta::default(), "hello".to_string());
+        assert_eq!(body, "hello");
+        assert_eq!(decode(body), (Meta::default(), "hello".to_string()));
+    }
+}
@@ -1,29 +1,74 @@
-use msg_store::store::Priority;
+use crate::{
+    api::{
+        msg::{
+            acknowledge,
+            deliver,
+            expires_at,
+            Handoff,
+            Limit,
+            get::{
+                ClaimData,
+                MsgData
+            },
+            insert_once,
+            msg_event,
+            release,
+            remove,
+            reprioritize,
+            valid_consumer_group
+        },
+        parse_duration,
+        parse_uuid
+    },
+    AppData,
+    events::EventKind,
+    leases::Token,
+    meta::{
+        Headers,
+        Meta,
+        msg_text
+    }
+};
+use msg_store::{
+    errors::Error,
+    GetOptions
+};
+use std::{
+    collections::BTreeSet,
+    time::Instant
+};
 use serde::{Deserialize,Serialize};
 use serde_json::{
     value::Value,
-    to_string,
     from_value,
     from_str
 };
 
-    
-#[derive(Deserialize, Serialize)]
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy)]
 #[serde(rename_all = "camelCase")]
 pub enum CMD {
     Post,
     Get,
-    Delete
+    Delete,
+    Put,
+    Subscribe,
+    Unsubscribe,
+    Credit,
+    Push,
+    Ack,
+    Nack
 }
 
 pub mod request {
     use super::*;
+    use serde::de::DeserializeOwned;
 
     #[derive(Deserialize, Serialize)]
     #[serde(rename_all = "camelCase")]
     pub struct Packet {
-        cmd: CMD,
-        data: Option<Value>
+        pub cmd: CMD,
+        pub data: Option<Value>
     }
 
     impl Packet {
@@ -37,43 +82,78 @@ pub mod request {
 
     }
 
-    pub fn from_value<T>(data: T) -> Result<T, String>
+    pub fn from_value<T: DeserializeOwned>(data: Option<Value>) -> Result<T, String>
     {
-        match from_value(data) {
+        match super::from_value(data.unwrap_or(Value::Null)) {
             Ok(data) => Ok(data),
             Err(error) => Err(error.to_string())
         }
-    } 
+    }
 
     #[derive(Deserialize, Serialize)]
+    #[serde(rename_all = "camelCase")]
     pub struct Post {
-        cmd: CMD,
-        priority: Priority,
-        msg: String
-    } 
+        pub priority: u32,
+        pub msg: Value,
+        pub ttl: Option<String>,
+        pub expires_at: Option<u64>,
+        pub not_before: Option<u64>,
+        pub idempotency_key: Option<String>,
+        pub headers: Option<Headers>
+    }
 
-    #[derive(Deserialize, Serialize)]
+    #[derive(Deserialize, Serialize, Default)]
+    #[serde(rename_all = "camelCase")]
     pub struct Get {
-        cmd: CMD
+        pub uuid: Option<String>,
+        pub priority: Option<u32>,
+        pub reverse: Option<bool>,
+        pub claim: Option<String>,
+        pub remove: Option<bool>,
+        pub limit: Option<u32>,
+        pub max_bytes: Option<u32>,
+        /// Only messages with all of these headers are handed out
+        pub headers: Option<Headers>,
+        pub consumer_group: Option<String>
+    }
+
+    #[derive(Deserialize, Serialize)]
+    pub struct Delete {
+        pub uuid: String
     }
 
     #[derive(Deserialize, Serialize)]
-    pub struct GetFrom {
-        cmd: CMD,
-        priority: Priority
+    #[serde(rename_all = "camelCase")]
+    pub struct Put {
+        pub uuid: String,
+        pub new_priority: u32
+    }
+
+    /// Starts pushing newly inserted messages to the client.
+    ///
+    /// The subscription can be narrowed to a single priority or a priority range,
+    /// and the client receives at most `credit` messages before it has to grant more.
+    #[derive(Deserialize, Serialize, Default)]
+    #[serde(rename_all = "camelCase")]
+    pub struct Subscribe {
+        pub priority: Option<u32>,
+        pub range_start: Option<u32>,
+        pub range_end: Option<u32>,
+        pub credit: Option<u32>,
+        /// Only messages with all of these headers are pushed
+        pub headers: Option<Headers>,
+        pub consumer_group: Option<String>
     }
 
     #[derive(Deserialize, Serialize)]
-    pub struct Delete {
-        cmd: CMD,
-        uuid: String
+    pub struct Credit {
+        pub credit: u32
     }
 
+    /// Used by both ack and nack.
     #[derive(Deserialize, Serialize)]
-    pub struct Put {
-        cmd: CMD,
-        uuid: String,
-        new_priority: Priority
+    pub struct Lease {
+        pub lease: String
     }
 
 }
@@ -82,24 +162,281 @@ pub mod response {
 
     use super::*;
 
+    pub enum ErrorCode {
+        ExceedesStoreMax,
+        ExceedesGroupMax,
+        LacksPriority,
+        InvalidPacket,
+        InvalidData,
+        StoreLockingError,
+        InternalError,
+        UnsupportedCmd,
+        NotFound
+    }
+    impl ErrorCode {
+        pub fn to_int(&self) -> u32 {
+            match self {
+                Self::ExceedesStoreMax => 1,
+                Self::ExceedesGroupMax => 2,
+                Self::LacksPriority => 3,
+                Self::InvalidPacket => 4,
+                Self::InvalidData => 5,
+                Self::StoreLockingError => 6,
+                Self::InternalError => 7,
+                Self::UnsupportedCmd => 8,
+                Self::NotFound => 9
+            }
+        }
+    }
+
     #[derive(Deserialize, Serialize)]
     pub struct Get {
-        cmd: CMD,
-	uuid: String
-    }	    
+        pub cmd: CMD,
+        pub data: Option<MsgData>
+    }
+
+    #[derive(Deserialize, Serialize)]
+    pub struct Claim {
+        pub cmd: CMD,
+        pub data: Option<ClaimData>
+    }
+
+    #[derive(Deserialize, Serialize)]
+    pub struct Batch {
+        pub cmd: CMD,
+        pub data: Vec<MsgData>
+    }
+
+    #[derive(Deserialize, Serialize)]
+    pub struct ClaimBatch {
+        pub cmd: CMD,
+        pub data: Vec<ClaimData>
+    }
 
     #[derive(Deserialize, Serialize)]
     #[serde(rename_all = "camelCase")]
     pub struct Post {
-        cmd: CMD,
-	uuid: String
+        pub cmd: CMD,
+        pub uuid: String
+    }
+
+    #[derive(Deserialize, Serialize)]
+    pub struct Delete {
+        pub cmd: CMD,
+        pub uuid: String
     }
 
     #[derive(Deserialize, Serialize)]
     pub struct Put {
-        cmd: CMD,
-        uuid: String
+        pub cmd: CMD,
+        pub uuid: String
+    }
+
+    /// Used by both ack and nack.
+    #[derive(Deserialize, Serialize)]
+    pub struct Lease {
+        pub cmd: CMD,
+        pub uuid: String
     }
-	
+
+    #[derive(Deserialize, Serialize)]
+    pub struct Subscription {
+        pub cmd: CMD,
+        pub credit: u32
+    }
+
+    #[derive(Deserialize, Serialize)]
+    pub struct Push {
+        pub cmd: CMD,
+        pub data: MsgData
+    }
+
+    #[derive(Deserialize, Serialize)]
+    pub struct Error {
+        pub cmd: Option<CMD>,
+        pub code: u32,
+        pub message: String
+    }
+    impl Error {
+        pub fn new(cmd: Option<CMD>, code: ErrorCode, message: &str) -> Error {
+            Error { cmd, code: code.to_int(), message: message.to_string() }
+        }
+        pub fn from_store_error(cmd: CMD, error: super::Error) -> Error {
+            match error {
+                super::Error::ExceedesStoreMax => Error::new(Some(cmd), ErrorCode::ExceedesStoreMax, "Message byte size exceeds the max byte size limit allowed by the store"),
+                super::Error::ExceedesGroupMax => Error::new(Some(cmd), ErrorCode::ExceedesGroupMax, "Message byte size exceeds the max byte size limit allowed by the group"),
+                super::Error::LacksPriority => Error::new(Some(cmd), ErrorCode::LacksPriority, "The store has reached max capcity and could not accept message"),
+                _ => Error::new(Some(cmd), ErrorCode::InternalError, "Internal server error")
+            }
+        }
+    }
+
+    #[derive(Deserialize, Serialize)]
+    #[serde(untagged)]
+    pub enum Packet {
+        Get(Get),
+        Claim(Claim),
+        Batch(Batch),
+        ClaimBatch(ClaimBatch),
+        Post(Post),
+        Delete(Delete),
+        Put(Put),
+        Lease(Lease),
+        Subscription(Subscription),
+        Push(Push),
+        Error(Error)
+    }
+
 }
 
+/// Handles a single packet and returns the reply that should be written back to the client.
+///
+/// The handler is transport agnostic, anything that can carry a JSON packet can use it.
+pub fn handle(data: &AppData, text: &str) -> response::Packet {
+    match request::Packet::from_str(text) {
+        Ok(packet) => handle_packet(data, packet),
+        Err(error) => response::Packet::Error(response::Error::new(None, response::ErrorCode::InvalidPacket, &error))
+    }
+}
+
+/// Handles an already parsed packet.
+///
+/// Commands that depend on connection state, such as subscriptions, have to be handled
+/// by the transport before reaching this point and are rejected here.
+pub fn handle_packet(data: &AppData, packet: request::Packet) -> response::Packet {
+    let result = match packet.cmd {
+        CMD::Post => handle_post(data, packet.data),
+        CMD::Get => handle_get(data, packet.data),
+        CMD::Delete => handle_delete(data, packet.data),
+        CMD::Put => handle_put(data, packet.data),
+        CMD::Ack => handle_ack(data, packet.data),
+        CMD::Nack => handle_nack(data, packet.data),
+        cmd => Err(response::Error::new(Some(cmd), response::ErrorCode::UnsupportedCmd, "Command is not supported on this connection"))
+    };
+    match result {
+        Ok(reply) => reply,
+        Err(error) => response::Packet::Error(error)
+    }
+}
+
+pub fn invalid_data(cmd: CMD, error: String) -> response::Error {
+    response::Error::new(Some(cmd), response::ErrorCode::InvalidData, &error)
+}
+
+pub fn store_locking_error(cmd: CMD) -> response::Error {
+    response::Error::new(Some(cmd), response::ErrorCode::StoreLockingError, "Internal server error")
+}
+
+fn handle_post(data: &AppData, packet_data: Option<Value>) -> Result<response::Packet, response::Error> {
+    let cmd = CMD::Post;
+    let body: request::Post = request::from_value(packet_data).map_err(|error| invalid_data(cmd, error))?;
+    let expires_at = expires_at(body.ttl.as_deref(), body.expires_at).map_err(|error| invalid_data(cmd, error))?;
+    let mut store = data.store.try_lock().map_err(|_error| store_locking_error(cmd))?;
+    let uuid = insert_once(data, &mut store, body.idempotency_key, body.priority, msg_text(&body.msg), Meta {
+        expires_at,
+        not_before: body.not_before,
+        dead_letter: None,
+        headers: body.headers.unwrap_or_default(),
+        binary: false,
+        json: !body.msg.is_string(),
+        compressed: None,
+        encrypted: false,
+        acked_by: BTreeSet::new()
+    })
+        .map_err(|error| response::Error::from_store_error(cmd, error))?;
+    Ok(response::Packet::Post(response::Post { cmd, uuid: uuid.to_string() }))
+}
+
+fn handle_get(data: &AppData, packet_data: Option<Value>) -> Result<response::Packet, response::Error> {
+    let cmd = CMD::Get;
+    let body: request::Get = match packet_data {
+        Some(packet_data) => request::from_value(Some(packet_data)).map_err(|error| invalid_data(cmd, error))?,
+        None => request::Get::default()
+    };
+    let mut options = GetOptions::default();
+    if let Some(uuid_string) = body.uuid {
+        let uuid = parse_uuid(&uuid_string).ok_or_else(|| invalid_data(cmd, "Invalid uuid".to_string()))?;
+        options.uuid = Some(uuid);
+    }
+    options.priority = body.priority;
+    options.reverse = body.reverse.unwrap_or(false);
+    let handoff = match (body.claim, body.remove.unwrap_or(false)) {
+        (Some(_claim), true) => {
+            return Err(invalid_data(cmd, "A message cannot be claimed and removed at once".to_string()));
+        },
+        (Some(claim), false) => Handoff::Claim(parse_duration(&claim).ok_or_else(|| invalid_data(cmd, "Invalid claim duration".to_string()))?),
+        (None, true) => Handoff::Remove,
+        (None, false) => Handoff::Peek
+    };
+    let batch = body.limit.is_some() || body.max_bytes.is_some();
+    let limit = Limit {
+        count: body.limit.unwrap_or(if batch { u32::MAX } else { 1 }),
+        max_bytes: body.max_bytes
+    };
+    if limit.count == 0 {
+        return Err(invalid_data(cmd, "Limit must be at least 1".to_string()));
+    }
+    let consumer_group = body.consumer_group.as_deref();
+    if !valid_consumer_group(data, consumer_group, handoff).map_err(|error| response::Error::from_store_error(cmd, error))? {
+        return Err(invalid_data(cmd, "Claiming or removing messages needs one of the store's consumer groups, if it has any".to_string()));
+    }
+    let mut store = data.store.try_lock().map_err(|_error| store_locking_error(cmd))?;
+    let headers = body.headers.unwrap_or_default();
+    let deliveries = deliver(data, &mut store, &options, &headers, consumer_group, handoff, limit).map_err(|error| response::Error::from_store_error(cmd, error))?;
+    let claimed = matches!(handoff, Handoff::Claim(_));
+    let mut deliveries = deliveries.into_iter();
+    let packet = match (claimed, batch) {
+        (false, false) => response::Packet::Get(response::Get { cmd, data: deliveries.next().map(MsgData::from_delivery) }),
+        (true, false) => response::Packet::Claim(response::Claim { cmd, data: deliveries.next().and_then(ClaimData::from_delivery) }),
+        (false, true) => response::Packet::Batch(response::Batch { cmd, data: deliveries.map(MsgData::from_delivery).collect() }),
+        (true, true) => response::Packet::ClaimBatch(response::ClaimBatch { cmd, data: deliveries.filter_map(ClaimData::from_delivery).collect() })
+    };
+    Ok(packet)
+}
+
+fn handle_delete(data: &AppData, packet_data: Option<Value>) -> Result<response::Packet, response::Error> {
+    let cmd = CMD::Delete;
+    let body: request::Delete = request::from_value(packet_data).map_err(|error| invalid_data(cmd, error))?;
+    let uuid = parse_uuid(&body.uuid).ok_or_else(|| invalid_data(cmd, "Invalid uuid".to_string()))?;
+    let mut store = data.store.try_lock().map_err(|_error| store_locking_error(cmd))?;
+    remove(data, &mut store, &uuid, EventKind::Deleted).map_err(|error| response::Error::from_store_error(cmd, error))?;
+    Ok(response::Packet::Delete(response::Delete { cmd, uuid: body.uuid }))
+}
+
+fn handle_put(data: &AppData, packet_data: Option<Value>) -> Result<response::Packet, response::Error> {
+    let cmd = CMD::Put;
+    let body: request::Put = request::from_value(packet_data).map_err(|error| invalid_data(cmd, error))?;
+    let uuid = parse_uuid(&body.uuid).ok_or_else(|| invalid_data(cmd, "Invalid uuid".to_string()))?;
+    let mut store = data.store.try_lock().map_err(|_error| store_locking_error(cmd))?;
+    let found = reprioritize(data, &mut store, &uuid, body.new_priority).map_err(|error| response::Error::from_store_error(cmd, error))?;
+    if !found {
+        return Err(response::Error::new(Some(cmd), response::ErrorCode::NotFound, "Message not found"));
+    }
+    Ok(response::Packet::Put(response::Put { cmd, uuid: body.uuid }))
+}
+
+fn handle_ack(data: &AppData, packet_data: Option<Value>) -> Result<response::Packet, response::Error> {
+    let cmd = CMD::Ack;
+    let body: request::Lease = request::from_value(packet_data).map_err(|error| invalid_data(cmd, error))?;
+    let token = Token::parse(&body.lease).ok_or_else(|| invalid_data(cmd, "Invalid lease".to_string()))?;
+    let mut store = data.store.try_lock().map_err(|_error| store_locking_error(cmd))?;
+    let released = data.leases.try_lock().map_err(|_error| store_locking_error(cmd))?.release(&token, Instant::now());
+    let lease = released.ok_or_else(|| response::Error::new(Some(cmd), response::ErrorCode::NotFound, "Lease not found or expired"))?;
+    let found = acknowledge(data, &mut store, &lease.uuid, lease.consumer_group.as_deref()).map_err(|error| response::Error::from_store_error(cmd, error))?;
+    if !found {
+        return Err(response::Error::new(Some(cmd), response::ErrorCode::NotFound, "Message not found"));
+    }
+    Ok(response::Packet::Lease(response::Lease { cmd, uuid: lease.uuid.to_string() }))
+}
+
+fn handle_nack(data: &AppData, packet_data: Option<Value>) -> Result<response::Packet, response::Error> {
+    let cmd = CMD::Nack;
+    let body: request::Lease = request::from_value(packet_data).map_err(|error| invalid_data(cmd, error))?;
+    let token = Token::parse(&body.lease).ok_or_else(|| invalid_data(cmd, "Invalid lease".to_string()))?;
+    let mut store = data.store.try_lock().map_err(|_error| store_locking_error(cmd))?;
+    let released = data.leases.try_lock().map_err(|_error| store_locking_error(cmd))?.release(&token, Instant::now());
+    let (msg, lease) = released.and_then(|lease| Some((msg_event(&store, &lease.uuid)?, lease)))
+        .ok_or_else(|| response::Error::new(Some(cmd), response::ErrorCode::NotFound, "Lease not found or expired"))?;
+    release(data, &mut store, &msg.uuid, lease.consumer_group.as_deref()).map_err(|error| response::Error::from_store_error(cmd, error))?;
+    Ok(response::Packet::Lease(response::Lease { cmd, uuid: msg.uuid.to_string() }))
+}
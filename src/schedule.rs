@@ -0,0 +1,131 @@
+use crate::config::{
+    FairDequeueConfig,
+    ShareUnit
+};
+use std::collections::BTreeMap;
+
+/// Keeps the passes apart when weights do not divide the cost evenly.
+const PASS_SCALE: u128 = 1 << 32;
+
+/// Shares out dequeues between priority groups in proportion to their weights.
+///
+/// Every weighted group has a pass that moves forward by the cost of each message it hands out
+/// divided by its weight, and the group with the lowest pass goes next, the higher priority on a tie.
+/// A group that has been idle starts from where the others are rather than from where it left off,
+/// so it cannot save up a burst.
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    weights: BTreeMap<u32, u32>,
+    unit: ShareUnit,
+    passes: BTreeMap<u32, u128>,
+    virtual_time: u128
+}
+
+impl Schedule {
+    pub fn new(config: Option<&FairDequeueConfig>) -> Schedule {
+        Schedule {
+            weights: config.iter()
+                .flat_map(|config| config.weights.iter())
+                .map(|group_weight| (group_weight.priority, group_weight.weight))
+                .collect(),
+            unit: config.and_then(|config| config.unit).unwrap_or(ShareUnit::Msgs),
+            passes: BTreeMap::new(),
+            virtual_time: 0
+        }
+    }
+
+    /// Whether the store shares out dequeues by weight.
+    pub fn is_weighted(&self) -> bool {
+        !self.weights.is_empty()
+    }
+
+    /// The pass the group would be served at next, None if the group has no weight.
+    pub fn pass(&self, priority: u32) -> Option<u128> {
+        if self.weights.get(&priority).is_none_or(|weight| *weight == 0) {
+            return None;
+        }
+        let pass = self.passes.get(&priority).copied().unwrap_or(0);
+        Some(pass.max(self.virtual_time))
+    }
+
+    /// Moves the pass of a group forward once it has handed out a message.
+    pub fn charge(&mut self, priority: u32, byte_size: u32) {
+        let (pass, weight) = match (self.pass(priority), self.weights.get(&priority)) {
+            (Some(pass), Some(weight)) => (pass, *weight as u128),
+            _ => {
+                return;
+            }
+        };
+        let cost = match self.unit {
+            ShareUnit::Msgs => 1,
+            ShareUnit::Bytes => byte_size.max(1) as u128
+        };
+        self.virtual_time = pass;
+        self.passes.insert(priority, pass + cost * PASS_SCALE / weight);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::GroupWeight;
+
+    fn schedule(weights: &[(u32, u32)], unit: ShareUnit) -> Schedule {
+        let weights = weights.iter().map(|(priority, weight)| GroupWeight { priority: *priority, weight: *weight }).collect();
+        Schedule::new(Some(&FairDequeueConfig { weights, unit: Some(unit) }))
+    }
+
+    /// Hands out one message from the busy group with the lowest pass, the higher priority on a tie.
+    fn dequeue(schedule: &mut Schedule, busy: &[u32], byte_size: impl Fn(u32) -> u32) -> u32 {
+        let mut best: Option<(u128, u32)> = None;
+        for priority in busy.iter().rev() {
+            let pass = schedule.pass(*priority).unwrap();
+            if best.is_none_or(|(best_pass, _priority)| pass < best_pass) {
+                best = Some((pass, *priority));
+            }
+        }
+        let (_pass, priority) = best.unwrap();
+        schedule.charge(priority, byte_size(priority));
+        priority
+    }
+
+    fn count(dequeued: &[u32], priority: u32) -> usize {
+        dequeued.iter().filter(|dequeued| **dequeued == priority).count()
+    }
+
+    #[test]
+    fn shares_dequeues_by_weight() {
+        let mut schedule = schedule(&[(3, 60), (2, 30), (1, 10)], ShareUnit::Msgs);
+        let dequeued: Vec<u32> = (0..100).map(|_| dequeue(&mut schedule, &[1, 2, 3], |_priority| 1)).collect();
+        assert_eq!(dequeued[0], 3);
+        assert_eq!((count(&dequeued, 3), count(&dequeued, 2), count(&dequeued, 1)), (60, 30, 10));
+    }
+
+    #[test]
+    fn unweighted_group_has_no_pass() {
+        let schedule = schedule(&[(2, 1), (1, 0)], ShareUnit::Msgs);
+        assert!(schedule.pass(2).is_some());
+        assert!(schedule.pass(1).is_none());
+        assert!(schedule.pass(3).is_none());
+    }
+
+    #[test]
+    fn idle_group_builds_up_no_credit() {
+        let mut schedule = schedule(&[(2, 50), (1, 50)], ShareUnit::Msgs);
+        for _ in 0..100 {
+            assert_eq!(dequeue(&mut schedule, &[2], |_priority| 1), 2);
+        }
+        let dequeued: Vec<u32> = (0..20).map(|_| dequeue(&mut schedule, &[1, 2], |_priority| 1)).collect();
+        assert_eq!(count(&dequeued, 1), 10);
+        assert!(dequeued.windows(3).all(|window| window.contains(&2)));
+    }
+
+    #[test]
+    fn shares_bytes_by_weight() {
+        let mut schedule = schedule(&[(2, 50), (1, 50)], ShareUnit::Bytes);
+        // priority 1 sends messages ten times the size of priority 2's
+        let byte_size = |priority: u32| if priority == 1 { 20 } else { 2 };
+        let dequeued: Vec<u32> = (0..110).map(|_| dequeue(&mut schedule, &[1, 2], byte_size)).collect();
+        assert_eq!((count(&dequeued, 2), count(&dequeued, 1)), (100, 10));
+    }
+}
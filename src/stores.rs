@@ -0,0 +1,131 @@
+use actix_web::{
+    dev::Payload,
+    error::{
+        ErrorInternalServerError,
+        ErrorNotFound
+    },
+    FromRequest,
+    HttpRequest,
+    rt,
+    web::Data
+};
+use crate::{
+    AppData,
+    config::StoreSettings,
+    init::open_store,
+    leases,
+    meta
+};
+use futures::future::{
+    ready,
+    Ready
+};
+use std::{
+    collections::BTreeMap,
+    ops::Deref,
+    path::PathBuf,
+    sync::RwLock
+};
+
+const MAX_NAME_LENGTH: usize = 64;
+
+/// The default store along with the named stores served under `/api/stores/{name}`.
+pub struct Stores {
+    pub default: Data<AppData>,
+    /// Read on every request to a named store, only written when a store is created
+    pub named: RwLock<BTreeMap<String, Data<AppData>>>,
+    location: Option<PathBuf>
+}
+
+impl Stores {
+    pub fn new(default: Data<AppData>, location: Option<PathBuf>) -> Stores {
+        Stores {
+            default,
+            named: RwLock::new(BTreeMap::new()),
+            location
+        }
+    }
+
+    /// Opens a named store.
+    ///
+    /// The store shares the config file and encryption key of the default store. It is not added to the named stores
+    /// and its background tasks are not started, so dropping it closes it again.
+    pub fn open(&self, name: &str, settings: StoreSettings) -> Result<Data<AppData>, String> {
+        let (store, idempotency_location) = open_store(&self.location, name)?;
+        let data = Data::new(AppData::open(
+            Some(name.to_string()),
+            store,
+            idempotency_location,
+            settings,
+            self.default.config_file.clone(),
+            #[cfg(feature = "level")]
+            self.default.cipher.clone()
+        )?);
+        Ok(data)
+    }
+}
+
+/// Starts the lease reaper and sweeper of a store, which keep it open for as long as the server runs.
+pub fn start(data: &Data<AppData>) {
+    rt::spawn(leases::reap(data.clone()));
+    rt::spawn(meta::sweep(data.clone()));
+}
+
+/// Store names are used as directory names, so they are kept to letters, digits, `-` and `_`.
+pub fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME_LENGTH
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// The store a request is for, picked by the `{name}` in its path, or the default store if there is none.
+pub struct ScopedData(Data<AppData>);
+
+impl ScopedData {
+    pub fn into_inner(self) -> Data<AppData> {
+        self.0
+    }
+
+    fn find(req: &HttpRequest) -> Result<ScopedData, actix_web::Error> {
+        let stores = match req.app_data::<Data<Stores>>() {
+            Some(stores) => stores,
+            None => {
+                return Err(ErrorInternalServerError("The stores are not set up"));
+            }
+        };
+        let name = match req.match_info().get("name") {
+            Some(name) => name,
+            None => {
+                return Ok(ScopedData(stores.default.clone()));
+            }
+        };
+        let named = match stores.named.read() {
+            Ok(named) => named,
+            Err(_error) => {
+                return Err(ErrorInternalServerError("Could not lock the stores"));
+            }
+        };
+        match named.get(name) {
+            Some(data) => Ok(ScopedData(data.clone())),
+            None => Err(ErrorNotFound("No store has that name"))
+        }
+    }
+}
+
+impl Deref for ScopedData {
+    type Target = Data<AppData>;
+
+    fn deref(&self) -> &Data<AppData> {
+        &self.0
+    }
+}
+
+impl FromRequest for ScopedData {
+    type Config = ();
+    type Error = actix_web::Error;
+    type Future = Ready<Result<ScopedData, actix_web::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(ScopedData::find(req))
+    }
+}